@@ -18,6 +18,9 @@ get_all_vars_for_arch() { # 1: arrayname, 2: varname
 for _integ in {ck,md5,sha{1,224,256,384,512},b2}; do
   get_all_vars_for_arch "all_${_integ}sums" "${_integ}sums"
 done
+for key in "${validpgpkeys[@]}"; do
+  echo "validpgpkey:${key}"
+done
 i=0
 for source in "${all_sources[@]}"; do
   echo '[source]'
@@ -39,6 +42,10 @@ for source in "${all_sources[@]}"; do
       ;;
     git)
       url=${url#git+}
+      if [[ $url == *#* ]]; then
+        fragment=${url#*#}
+        echo "fragment:${fragment%%\?*}"
+      fi
       url=${url%%#*}
       url=${url%%\?*}
       ;;
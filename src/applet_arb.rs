@@ -3,7 +3,7 @@ use std::{collections::HashMap, ffi::OsString};
 
 use clap::Parser;
 
-use crate::{error::Result, source::{Proxy, git::Gmr}, identity::IdentityActual, config::{Pkgbuild, DepHash}};
+use crate::{error::Result, source::{DomainLimits, HttpAuth, NetfileMirrors, Proxy, git::{Gmr, GitRules}}, identity::IdentityActual, config::{Pkgbuild, DepHash, Retention}};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -16,8 +16,8 @@ struct Args {
     #[arg(short, long)]
     build: Vec<String>,
 
-    /// HTTP proxy to retry for git updating and http(s)
-    /// netfiles if attempt without proxy failed
+    /// Default proxy to retry for git updating and netfiles if attempt
+    /// without proxy failed, for domains not matched by proxy_rules
     #[arg(short, long, default_value_t = String::new())]
     proxy: String,
 
@@ -30,7 +30,8 @@ struct Args {
     #[arg(short='P', long, default_value_t)]
     holdpkg: bool,
 
-    /// Hold versions of git sources, do not update them
+    /// Hold versions of git and other VCS sources, and netfile sources
+    /// without integrity checksums, do not update them
     #[arg(short='G', long, default_value_t)]
     holdgit: bool,
 
@@ -46,6 +47,22 @@ struct Args {
     #[arg(short='C', long, default_value_t)]
     noclean: bool,
 
+    /// Optional packages to rebuild even if their pkgid was already built, the
+    /// old build is kept until the new one finishes
+    #[arg(short, long)]
+    rebuild: Vec<String>,
+
+    /// Rebuild all packages even if their pkgid was already built
+    #[arg(long, default_value_t)]
+    rebuild_all: bool,
+
+    /// Only print what would be built and why, as a table and in JSON under
+    /// logs/, from PKGBUILDs and sources as already cached and deps in the
+    /// host sync DBs as last refreshed, fetching and changing nothing but
+    /// logs, implies --nobuild and --noclean
+    #[arg(long, default_value_t)]
+    plan: bool,
+
     /// Disallow any network connection during makepkg's build routine
     #[arg(short='N', long, default_value_t)]
     nonet: bool,
@@ -54,10 +71,10 @@ struct Args {
     #[arg(short='d', long, default_value_t)]
     drop: String,
 
-    /// Prefix of a 7Ji/git-mirrorer instance, e.g. git://gmr.lan,
-    /// The mirror would be tried first before actual git remote
-    #[arg(short='g', long, default_value_t)]
-    gmr: String,
+    /// Prefix of a 7Ji/git-mirrorer instance, e.g. git://gmr.lan, could be
+    /// repeated, the mirrors would be tried in order before actual git remote
+    #[arg(short='g', long)]
+    gmr: Vec<String>,
 
     /// The GnuPG key ID used to sign packages
     #[arg(short, long, default_value_t)]
@@ -75,8 +92,19 @@ struct Settings {
     nobuild: bool,
     noclean: bool,
     nonet: bool,
+    plan: bool,
+    rebuild: Vec<String>,
+    rebuild_all: bool,
     gmr: Option<Gmr>,
+    mirrors: NetfileMirrors,
+    auth: HttpAuth,
+    limits: DomainLimits,
+    git_rules: GitRules,
+    git_maintenance: u64,
+    pgp_keyring: String,
     dephash: DepHash,
+    retention: Retention,
+    pins: HashMap<String, String>,
     sign: String,
     homebinds: Vec<String>,
     terminal: bool
@@ -97,19 +125,23 @@ impl Settings {
             config.pkgbuilds.retain(|name, _|arg.build.contains(name));
         }
         let proxy = if ! arg.proxy.is_empty() {
-            Some(Proxy::new(&arg.proxy, arg.lazyproxy))
-        } else if ! config.proxy.is_empty() {
-            Some(Proxy::new(&config.proxy, config.lazyproxy))
+            Proxy::new(&arg.proxy, arg.lazyproxy, &config.proxy_rules,
+                &config.no_proxy)
+        } else {
+            Proxy::new(&config.proxy, config.lazyproxy, &config.proxy_rules,
+                &config.no_proxy)
+        };
+        let gmr = if ! arg.gmr.is_empty() {
+            Gmr::init(&arg.gmr)
         } else {
-            None
+            Gmr::init(&config.gmr.to_vec())
         };
-        let gmr = if ! arg.gmr.is_empty() { 
-            Some(Gmr::init(&arg.gmr))
-        } else if ! config.gmr.is_empty() { 
-            Some(Gmr::init(&config.gmr))
+        let netrc = if config.netrc.is_empty() {
+            actual_identity.home().join(".netrc")
         } else {
-            None
+            std::path::PathBuf::from(&config.netrc)
         };
+        let auth = HttpAuth::new(&config.http_headers, &netrc)?;
         Ok(Settings {
             actual_identity,
             pkgbuilds_config: config.pkgbuilds,
@@ -118,11 +150,24 @@ impl Settings {
             holdpkg: arg.holdpkg || config.holdpkg,
             holdgit: arg.holdgit || config.holdgit,
             skipint: arg.skipint || config.skipint,
-            nobuild: arg.nobuild || config.nobuild,
-            noclean: !arg.build.is_empty() || arg.noclean || config.noclean,
+            nobuild: arg.plan || arg.nobuild || config.nobuild,
+            noclean: !arg.build.is_empty() || arg.plan || arg.noclean ||
+                config.noclean,
             nonet: arg.nonet || config.nonet,
+            plan: arg.plan,
+            rebuild: arg.rebuild,
+            rebuild_all: arg.rebuild_all || config.rebuild_all,
             gmr,
+            mirrors: NetfileMirrors::new(
+                &config.netfile_mirrors, &config.netfile_cache),
+            auth,
+            limits: DomainLimits::new(&config.domain_limits),
+            git_rules: GitRules::new(&config.git_depths, &config.git_credentials),
+            git_maintenance: config.git_maintenance,
+            pgp_keyring: config.pgp_keyring,
             dephash: config.dephash,
+            retention: config.retention,
+            pins: config.pins,
             sign: if arg.sign.is_empty() { config.sign } else { arg.sign },
             homebinds: config.homebinds,
             terminal: is_terminal::is_terminal(std::io::stdout())
@@ -130,24 +175,49 @@ impl Settings {
     }
 
     fn work(&self) -> Result<()> {
-        crate::filesystem::create_layout()?;
-        let mut pkgbuilds =
+        crate::filesystem::create_layout(self.plan)?;
+        // Maintenance could reclone, which neither should do
+        if ! self.plan && ! self.holdgit {
+            crate::source::git::maintain(self.git_maintenance,
+                &self.actual_identity, self.gmr.as_ref(), &self.git_rules,
+                &self.limits, self.proxy.as_ref(), self.terminal);
+        }
+        let mut pkgbuilds = if self.plan {
+            crate::pkgbuild::PKGBUILDs::from_config_cached(
+                &self.pkgbuilds_config, &self.homebinds)?
+        } else {
             crate::pkgbuild::PKGBUILDs::from_config_healthy(
                 &self.pkgbuilds_config, self.holdpkg,
                 self.noclean, self.proxy.as_ref(),
-                self.gmr.as_ref(), &self.homebinds, self.terminal
-            )?;
-        let root = pkgbuilds.prepare_sources(
-            &self.actual_identity, &self.basepkgs, self.holdgit,
-            self.skipint, self.noclean, self.proxy.as_ref(),
-            self.gmr.as_ref(), &self.dephash, self.terminal)?;
-        let r = crate::build::maybe_build(&pkgbuilds,
+                self.gmr.as_ref(), &self.git_rules, &self.auth, &self.limits,
+                &self.homebinds, self.terminal
+            )?
+        };
+        pkgbuilds.mark_rebuild(&self.rebuild, self.rebuild_all);
+        pkgbuilds.mark_pins(&self.pins);
+        let cache_settings = crate::source::CacheSettings {
+            actual_identity: &self.actual_identity,
+            holdgit: self.holdgit,
+            skipint: self.skipint,
+            proxy: self.proxy.as_ref(),
+            gmr: self.gmr.as_ref(),
+            mirrors: &self.mirrors,
+            auth: &self.auth,
+            limits: &self.limits,
+            rules: &self.git_rules,
+            terminal: self.terminal,
+        };
+        let root = pkgbuilds.prepare_sources(&cache_settings, &self.basepkgs,
+            self.noclean, &self.pgp_keyring, &self.dephash, self.plan)?;
+        let r = crate::build::maybe_build(&mut pkgbuilds,
             root, &self.actual_identity, self.nobuild, self.nonet,
-            &self.sign);
+            &self.sign, &self.dephash);
         let _ = std::fs::remove_dir("build");
-        pkgbuilds.link_pkgs();
+        if ! self.plan {
+            pkgbuilds.link_pkgs();
+        }
         if ! self.noclean {
-            pkgbuilds.clean_pkgdir();
+            pkgbuilds.clean_pkgdir(&self.retention);
         }
         r
     }
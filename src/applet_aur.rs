@@ -0,0 +1,76 @@
+use std::{
+        ffi::OsString,
+        path::PathBuf,
+    };
+
+use clap::Parser;
+
+use crate::{
+        config::{
+            Config,
+            Pkgbuild,
+        },
+        error::Result,
+        identity::IdentityActual,
+        source::{
+            aur::{
+                AUR_RPC,
+                Health,
+            },
+            HttpAuth,
+        },
+    };
+
+#[derive(Parser, Debug)]
+#[command(author, version,
+    about = "Report orphaned, flagged and vanished AUR packages in the config",
+    long_about = None)]
+struct Args {
+    /// Optional config.yaml file
+    #[arg(default_value_t = String::from("config.yaml"))]
+    config: String,
+
+    /// Prefix of the AUR RPC, e.g. a local mock of it
+    #[arg(short, long, default_value_t = String::from(AUR_RPC))]
+    rpc: String,
+}
+
+/// Pkgbases of PKGBUILDs from AUR, either by the alias or by full URL
+fn aur_pkgbases(config: &Config) -> Vec<String> {
+    const AUR_PREFIX: &str = "https://aur.archlinux.org/";
+    let mut pkgbases: Vec<String> = config.pkgbuilds.iter().filter_map(
+        |(name, pkgbuild)| {
+            let url = match pkgbuild {
+                Pkgbuild::Simple(url) => url,
+                Pkgbuild::Complex { url, .. } => url,
+            };
+            if url == "AUR" {
+                Some(name.clone())
+            } else {
+                url.strip_prefix(AUR_PREFIX).map(|path| path
+                    .trim_end_matches('/').trim_end_matches(".git").to_string())
+            }
+        }).collect();
+    pkgbases.sort_unstable();
+    pkgbases.dedup();
+    pkgbases
+}
+
+pub(crate) fn main<I, S>(args: I) -> Result<()>
+where
+    I: Iterator<Item = S>,
+    S: Into<OsString> + Clone,
+{
+    let arg: Args = clap::Parser::parse_from(args);
+    let config = Config::from_file(&arg.config)?;
+    let netrc = if config.netrc.is_empty() {
+        IdentityActual::new_no_drop()?.home().join(".netrc")
+    } else {
+        PathBuf::from(&config.netrc)
+    };
+    let auth = HttpAuth::new(&config.http_headers, &netrc)?;
+    let pkgbases = aur_pkgbases(&config);
+    log::info!("Checking {} AUR packages on '{}'", pkgbases.len(), arg.rpc);
+    Health::check(arg.rpc.trim_end_matches('/'), &pkgbases, &auth)?.print();
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::ffi::OsString;
+
+use clap::Parser;
+
+use crate::{
+        error::Result,
+        pkgbuild::pin,
+    };
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Pin a pkgbase to a build under pkgs/",
+    long_about = None)]
+struct Args {
+    /// The pkgbase to pin, list all pins if omitted
+    pkgbase: Option<String>,
+
+    /// The pkgid under pkgs/ to pin to, list builds of the pkgbase if omitted
+    pkgid: Option<String>,
+
+    /// Remove the pin of the pkgbase instead
+    #[arg(short, long, default_value_t)]
+    unpin: bool,
+}
+
+pub(crate) fn main<I, S>(args: I) -> Result<()>
+where
+    I: Iterator<Item = S>,
+    S: Into<OsString> + Clone,
+{
+    let arg: Args = clap::Parser::parse_from(args);
+    match (arg.pkgbase, arg.pkgid) {
+        (Some(pkgbase), _) if arg.unpin => pin::unset(&pkgbase),
+        (Some(pkgbase), Some(pkgid)) => pin::set(&pkgbase, &pkgid),
+        (pkgbase, _) => {
+            pin::list(pkgbase.as_deref());
+            Ok(())
+        },
+    }
+}
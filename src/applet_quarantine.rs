@@ -0,0 +1,35 @@
+use std::ffi::OsString;
+
+use clap::Parser;
+
+use crate::{
+        error::Result,
+        source::quarantine,
+    };
+
+#[derive(Parser, Debug)]
+#[command(author, version,
+    about = "List or purge downloads quarantined for failing integrity checks",
+    long_about = None)]
+struct Args {
+    /// Purge the quarantined files instead of listing them
+    #[arg(short, long, default_value_t)]
+    purge: bool,
+
+    /// Names of quarantined files to purge, all if omitted
+    names: Vec<String>,
+}
+
+pub(crate) fn main<I, S>(args: I) -> Result<()>
+where
+    I: Iterator<Item = S>,
+    S: Into<OsString> + Clone,
+{
+    let arg: Args = clap::Parser::parse_from(args);
+    if arg.purge {
+        quarantine::purge(&arg.names)
+    } else {
+        quarantine::list();
+        Ok(())
+    }
+}
@@ -1,27 +1,45 @@
 mod builder;
 mod dir;
 
-use crate::error::Result;
+use crate::{
+        error::Result,
+        root::CommonRoot,
+    };
 
 pub(crate) fn maybe_build(
-    pkgbuilds: &crate::pkgbuild::PKGBUILDs,
+    pkgbuilds: &mut crate::pkgbuild::PKGBUILDs,
     root: Option<crate::root::BaseRoot>,
     actual_identity: &crate::identity::IdentityActual,
     nobuild: bool,
     nonet: bool,
-    sign: &str
+    sign: &str,
+    dephash_strategy: &crate::config::DepHash
 ) -> Result<()>
 {
-    if let Some(_root) = root {
+    if let Some(root) = root {
         if nobuild {
             return Ok(())
         }
-        match crate::pacman::split_pkgbuilds(pkgbuilds) {
+        match pkgbuilds.layers() {
             Ok(layers) => {
-                for layer in layers {
+                // Sonames of what earlier layers just built could change the
+                // pkgids of later ones
+                let db_handle = match dephash_strategy {
+                    crate::config::DepHash::Soname => Some(
+                        crate::pacman::DbHandle::new(root.path())?),
+                    _ => None,
+                };
+                for (id, layer) in layers.iter().enumerate() {
+                    if let Some(db_handle) = &db_handle {
+                        if id > 0 {
+                            pkgbuilds.rehash_sonames_layer(
+                                layer, db_handle, true)?
+                        }
+                    }
+                    let layer = layer.iter().map(
+                        |id| &pkgbuilds.0[*id]).collect();
                     builder::build_any_needed_layer(
                         &layer, &actual_identity, nonet, sign)?
-
                 }
             },
             Err(_) => builder::build_any_needed(
@@ -29,4 +47,4 @@ pub(crate) fn maybe_build(
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -9,6 +9,7 @@ use crate::error::Result;
 pub(crate) enum DepHash {
     Strict, // dep + makedep
     Loose,  // dep
+    Soname, // lib*.so provides of dep + makedep
     None,   // none
 }
 
@@ -33,6 +34,87 @@ pub(crate) enum Pkgbuild {
     },
 }
 
+/// A single prefix or a list of them, in order
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Prefixes {
+    One (String),
+    Many (Vec<String>),
+}
+
+impl Default for Prefixes {
+    fn default() -> Self {
+        Self::Many(Vec::new())
+    }
+}
+
+impl Prefixes {
+    pub(crate) fn to_vec(&self) -> Vec<String> {
+        match self {
+            Self::One(prefix) => vec![prefix.clone()],
+            Self::Many(prefixes) => prefixes.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Default)]
+pub(crate) struct Retention {
+    #[serde(default)]
+    pub(crate) keep_last: usize, // previous pkgids to keep per pkgbase
+    #[serde(default)]
+    pub(crate) keep_days: u64,  // keep pkgids younger than this
+    #[serde(default)]
+    pub(crate) archive: String, // move expired pkgids here if set
+}
+
+/// Where to find credentials for git remotes, secrets themselves never live
+/// in the config
+#[derive(Debug, PartialEq, Deserialize, Clone, Default)]
+pub(crate) struct GitCredential {
+    #[serde(default)]
+    pub(crate) username: String, // default: from URL, then 'git'
+    #[serde(default)]
+    pub(crate) ssh_key: String, // path to private key
+    #[serde(default)]
+    pub(crate) ssh_agent: bool, // use the agent at SSH_AUTH_SOCK
+    #[serde(default)]
+    pub(crate) token_file: String, // file containing password or token
+    #[serde(default)]
+    pub(crate) token_env: String, // env containing password or token
+}
+
+/// A header added to HTTP(S) requests to a host, the value is the literal
+/// part followed by the trimmed content of the file or env if set
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct HttpHeader {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) value: String,
+    #[serde(default)]
+    pub(crate) file: String,
+    #[serde(default)]
+    pub(crate) env: String,
+}
+
+/// Limits of downloads and git fetches from a domain, 0 for the default
+#[derive(Debug, PartialEq, Deserialize, Clone, Default)]
+pub(crate) struct DomainLimit {
+    #[serde(default)]
+    pub(crate) threads: usize, // default: 10, 1 for AUR
+    #[serde(default)]
+    pub(crate) rps: f64, // requests started per second, default: unlimited
+    #[serde(default)]
+    pub(crate) bandwidth: u64, // KiB/s, default: unlimited
+}
+
+/// Proxies for hosts matching any of the domain globs, tried in order after
+/// the direct tries, 'direct' alone to never proxy them
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct ProxyRule {
+    pub(crate) domains: Vec<String>,
+    pub(crate) proxies: Vec<String>,
+}
+
 #[derive(Debug, PartialEq, Deserialize)]
 pub(crate) struct Config {
     #[serde(default)]
@@ -46,19 +128,47 @@ pub(crate) struct Config {
     #[serde(default)]
     pub(crate) noclean: bool,
     #[serde(default)]
+    pub(crate) rebuild_all: bool,
+    #[serde(default)]
     pub(crate) nonet: bool,
     #[serde(default)]
     pub(crate) sign: String,
     #[serde(default)]
-    pub(crate) gmr: String,
+    pub(crate) gmr: Prefixes,
     #[serde(default)]
     pub(crate) proxy: String,
     #[serde(default)]
     pub(crate) lazyproxy: usize,
+    #[serde(default)]
+    pub(crate) proxy_rules: Vec<ProxyRule>,
+    #[serde(default)]
+    pub(crate) no_proxy: Vec<String>,
+    #[serde(default)]
+    pub(crate) netfile_mirrors: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(crate) netfile_cache: String,
+    #[serde(default)]
+    pub(crate) http_headers: HashMap<String, Vec<HttpHeader>>,
+    #[serde(default)]
+    pub(crate) domain_limits: HashMap<String, DomainLimit>,
+    #[serde(default)]
+    pub(crate) netrc: String, // default: .netrc in the actual user's home
+    #[serde(default)]
+    pub(crate) git_depths: HashMap<String, i32>,
+    #[serde(default)]
+    pub(crate) git_credentials: HashMap<String, GitCredential>,
+    #[serde(default = "default_git_maintenance")]
+    pub(crate) git_maintenance: u64, // days between gc of git repos, 0 never
+    #[serde(default = "default_pgp_keyring")]
+    pub(crate) pgp_keyring: String,
     #[serde(default = "default_basepkgs")]
     pub(crate) basepkgs: Vec<String>,
     #[serde(default)]
     pub(crate) dephash: DepHash,
+    #[serde(default)]
+    pub(crate) retention: Retention,
+    #[serde(default)]
+    pub(crate) pins: HashMap<String, String>,
     pub(crate) pkgbuilds: std::collections::HashMap<String, Pkgbuild>,
     #[serde(default = "default_home_binds")]
     pub(crate) homebinds: Vec<String>,
@@ -68,6 +178,14 @@ fn default_basepkgs() -> Vec<String> {
     vec![String::from("base-devel")]
 }
 
+fn default_git_maintenance() -> u64 {
+    7
+}
+
+fn default_pgp_keyring() -> String {
+    String::from("keyring")
+}
+
 fn default_home_binds() -> Vec<String> {
     Vec::new()
 }
@@ -216,13 +216,19 @@ where
     r
 }
 
-pub(crate) fn create_layout() -> Result<()> {
-    create_dirs_allow_existing(["build", "logs", "pkgs", "sources"])?;
-    remove_dirs_allow_non_existing(["pkgs/updated", "pkgs/latest"])?;
+/// The links in pkgs/updated and pkgs/latest are reset unless kept, e.g. for
+/// a plan which would not link them again
+pub(crate) fn create_layout(keep_links: bool) -> Result<()> {
+    create_dirs_allow_existing(["build", "logs", "pkgs", "sources", "state"])?;
+    if ! keep_links {
+        remove_dirs_allow_non_existing(["pkgs/updated", "pkgs/latest"])?;
+    }
     create_dirs_under_allow_existing(["updated", "latest"], "pkgs")?;
+    create_dirs_under_allow_existing(["depends", "pins"], "state")?;
     create_dirs_under_allow_existing([
-        "file-ck", "file-md5", "file-sha1", "file-sha224", "file-sha256",
-        "file-sha384", "file-sha512", "file-b2", "git", "PKGBUILD"], 
+        "blobs", "index-ck", "index-md5", "index-sha1", "index-sha224",
+        "index-sha256", "index-sha384", "index-sha512", "index-b2",
+        "file-url", "git", "PKGBUILD", "bzr", "fossil", "hg", "svn"],
         "sources")
 }
 
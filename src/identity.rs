@@ -416,6 +416,16 @@ impl IdentityActual {
         }
     }
 
+    /// The sudo user if started with sudo, otherwise the current user, for
+    /// applets that only need its home and never drop
+    pub(crate) fn new_no_drop() -> Result<Self> {
+        if std::env::var_os("SUDO_UID").is_some() {
+            Self::new_from_sudo()
+        } else {
+            Self::new(getuid(), getgid())
+        }
+    }
+
     pub(crate) fn new_and_drop(id_pair: &str) -> Result<Self> {
         let r = if id_pair.is_empty() {
             Self::new_from_sudo()
@@ -16,13 +16,15 @@ use crate::error::{
 pub(crate) enum LogType {
     Build,
     Extract,
+    Plan,
 }
 
 impl Display for LogType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", match self {
             Self::Build => "build",
-            Self::Extract => "extract"
+            Self::Extract => "extract",
+            Self::Plan => "plan",
         })
     }
 }
@@ -34,6 +36,13 @@ pub(crate) struct LogFile {
 
 impl LogFile {
     pub(crate) fn new<S: AsRef<str>>(log_type: LogType, id: S) -> Result<Self> {
+        Self::new_with_ext(log_type, id, "log")
+    }
+
+    pub(crate) fn new_with_ext<S: AsRef<str>>(
+        log_type: LogType, id: S, ext: &str
+    ) -> Result<Self>
+    {
         const DATE_TIME_FORMAT: &[time::format_description::FormatItem<'_>] = 
             time::macros::format_description!(
                 "[year][month][day]_[hour][minute][second]");
@@ -46,8 +55,8 @@ impl LogFile {
                 return Err(Into::<time::Error>::into(e).into())
             },
         };
-        let path = PathBuf::from(format!("logs/{}_{}_{}.log", 
-            time_formatted, log_type, id.as_ref()));
+        let path = PathBuf::from(format!("logs/{}_{}_{}.{}", 
+            time_formatted, log_type, id.as_ref(), ext));
         let file = File::create(&path).map_err(Error::from)?;
         log::info!("Log to {} '{}' is stored at '{}'", log_type, id.as_ref(), 
                     path.display());
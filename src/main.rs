@@ -1,8 +1,11 @@
 use std::{env::ArgsOs, ffi::OsString, path::PathBuf, os::unix::ffi::OsStrExt};
 
 mod applet_arb;
+mod applet_aur;
 mod applet_builder;
 mod applet_init;
+mod applet_pin;
+mod applet_quarantine;
 
 mod build;
 mod child;
@@ -57,7 +60,10 @@ fn dispatch(mut args: ArgsOs) -> Result<()> {
         b"arb_multi" | b"arb-multi" | b"multi"  => dispatch(args),
         b"arb" | b"arch_repo_builder" | b"arch-repo-builder" => 
                     applet_arb::main(private_args(args)),
+        b"aur" => applet_aur::main(private_args(args)),
         b"init" => applet_init::main(private_args(args)),
+        b"pin" => applet_pin::main(private_args(args)),
+        b"quarantine" => applet_quarantine::main(private_args(args)),
         other => {
             log::error!("Unknown applet {}", String::from_utf8_lossy(other));
             Err(Error::InvalidArgument)
@@ -4,5 +4,8 @@ mod depends;
 mod interdep;
 
 pub(crate) use db::DbHandle;
-pub(crate) use depends::Depends;
+pub(crate) use depends::{
+    Depends,
+    Satisfier,
+};
 pub(crate) use interdep::split_pkgbuilds;
\ No newline at end of file
@@ -5,7 +5,9 @@ use std::{
 
 use alpm::{
         Alpm,
+        LoadedPackage,
         Package,
+        SigLevel,
     };
 
 
@@ -78,6 +80,31 @@ impl DbHandle {
         pkg_satisfier
     }
 
+    /// Packages built by us in the pkgdir, to take their provides
+    pub(crate) fn load_pkgs(&self, pkgdir: &Path) -> Vec<LoadedPackage<'_>> {
+        let readdir = match pkgdir.read_dir() {
+            Ok(readdir) => readdir,
+            Err(_) => return vec![],
+        };
+        let mut pkgs = vec![];
+        for entry in readdir.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if ! name.contains(".pkg.tar") || name.ends_with(".sig") {
+                continue
+            }
+            match self.alpm_handle.pkg_load(path.as_os_str().as_bytes(),
+                false, SigLevel::NONE)
+            {
+                Ok(pkg) => pkgs.push(pkg),
+                Err(e) => log::warn!("Failed to load built package '{}': {}",
+                    path.display(), e),
+            }
+        }
+        pkgs
+    }
+
     pub(super) fn is_installed<S: AsRef<str>>(&self, pkg: S) -> bool {
         match self.alpm_handle.localdb().pkg(pkg.as_ref()) {
             Ok(_) => true,
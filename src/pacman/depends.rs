@@ -1,10 +1,20 @@
 use std::{
         ffi::OsStr,
+        fs::File,
         hash::Hasher,
+        io::{
+            BufRead,
+            BufReader,
+            Write,
+        },
+        path::Path,
         process::Command,
     };
 
-use alpm::Package;
+use alpm::{
+        LoadedPackage,
+        Pkg,
+    };
 
 use xxhash_rust::xxh3;
 
@@ -23,16 +33,26 @@ use crate::{
     };
 
 
+/// The package that satisfied a dep when the dephash was calculated
+#[derive(Clone)]
+pub(crate) struct Satisfier {
+    pub(crate) dep: String,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) hash: u64,
+}
+
 #[derive(Clone)]
 pub(crate) struct Depends {
     pub(crate) deps: Vec<String>,
     pub(crate) makedeps: Vec<String>,
     pub(crate) needs: Vec<String>,
     pub(crate) hash: u64,
+    pub(crate) satisfiers: Vec<Satisfier>,
 }
 
 
-fn update_hash_from_pkg(hash: &mut xxh3::Xxh3, pkg: Package<'_>) {
+fn update_hash_from_pkg(hash: &mut xxh3::Xxh3, pkg: &Pkg<'_>) {
     if let Some(sig) = pkg.base64_sig() {
         hash.update(sig.as_bytes());
         return
@@ -53,21 +73,85 @@ fn update_hash_from_pkg(hash: &mut xxh3::Xxh3, pkg: Package<'_>) {
     // we will add the possibility of fake-positive
 }
 
+/// A package built by us satisfying the dep by name or provides, ignoring the
+/// version constraint as whatever we built is what would be used
+fn find_built<'a, 'h>(built: &'a [LoadedPackage<'h>], dep: &str)
+    -> Option<&'a Pkg<'h>>
+{
+    let name = dep.split(['<', '>', '=']).next().unwrap_or(dep);
+    built.iter().map(|pkg| &**pkg).find(|pkg| pkg.name() == name ||
+        pkg.provides().iter().any(|provide| provide.name() == name))
+}
+
+/// Only the sonames a package provides, i.e. `libfoo.so=1-64`, so the hash
+/// only changes when a library bumps its soname
+fn update_hash_from_pkg_sonames(hash: &mut xxh3::Xxh3, pkg: &Pkg<'_>) {
+    let mut sonames: Vec<String> = pkg.provides().iter().filter_map(
+        |provide| {
+            let name = provide.name();
+            if name.starts_with("lib") && name.contains(".so") &&
+                provide.version().is_some()
+            {
+                Some(provide.to_string())
+            } else {
+                None
+            }
+        }).collect();
+    sonames.sort_unstable();
+    for soname in sonames.iter() {
+        hash.update(soname.as_bytes());
+        hash.update(b"\n");
+    }
+}
+
+impl Satisfier {
+    fn from_pkg(dep: &str, pkg: &Pkg<'_>, sonames_only: bool) -> Self {
+        let mut hash = xxh3::Xxh3::new();
+        if sonames_only {
+            update_hash_from_pkg_sonames(&mut hash, pkg)
+        } else {
+            update_hash_from_pkg(&mut hash, pkg)
+        }
+        Self {
+            dep: dep.to_string(),
+            name: pkg.name().to_string(),
+            version: pkg.version().to_string(),
+            hash: hash.finish(),
+        }
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut it = line.splitn(4, '\t');
+        let dep = it.next()?.to_string();
+        let name = it.next()?.to_string();
+        let version = it.next()?.to_string();
+        let hash = u64::from_str_radix(it.next()?, 16).ok()?;
+        Some(Self { dep, name, version, hash })
+    }
+}
+
+impl std::fmt::Display for Satisfier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
 impl Depends {
     fn needed_and_strict_hash(&mut self, db_handle: &DbHandle) -> Result<()>
     {
         let mut hash_box = Box::new(xxh3::Xxh3::new());
         let hash = hash_box.as_mut();
-        for dep in self.deps.iter().chain(self.makedeps.iter()) {
-            let dep = match db_handle.find_satisfier(dep) {
+        for dep_name in self.deps.iter().chain(self.makedeps.iter()) {
+            let dep = match db_handle.find_satisfier(dep_name) {
                 Some(dep) => dep,
                 None => {
-                    log::error!("Warning: dep {} not found", dep);
-                    return Err(Error::DependencyMissing(vec![dep.as_str().into()]))
+                    log::error!("Warning: dep {} not found", dep_name);
+                    return Err(Error::DependencyMissing(vec![dep_name.clone()]))
                 },
             };
             self.needs.push(dep.name().to_string());
-            update_hash_from_pkg(hash, dep);
+            self.satisfiers.push(Satisfier::from_pkg(dep_name, &dep, false));
+            update_hash_from_pkg(hash, &dep);
         }
         self.hash = hash.finish();
         Ok(())
@@ -77,16 +161,17 @@ impl Depends {
     {
         let mut hash_box = Box::new(xxh3::Xxh3::new());
         let hash = hash_box.as_mut();
-        for dep in self.deps.iter() {
-            let dep = match db_handle.find_satisfier(dep) {
+        for dep_name in self.deps.iter() {
+            let dep = match db_handle.find_satisfier(dep_name) {
                 Some(dep) => dep,
                 None => {
-                    log::error!("Warning: dep {} not found", dep);
-                    return Err(Error::DependencyMissing(vec![dep.as_str().into()]))
+                    log::error!("Warning: dep {} not found", dep_name);
+                    return Err(Error::DependencyMissing(vec![dep_name.clone()]))
                 },
             };
             self.needs.push(dep.name().to_string());
-            update_hash_from_pkg(hash, dep);
+            self.satisfiers.push(Satisfier::from_pkg(dep_name, &dep, false));
+            update_hash_from_pkg(hash, &dep);
         }
         for dep in self.makedeps.iter() {
             let dep = match db_handle.find_satisfier(dep) {
@@ -102,6 +187,36 @@ impl Depends {
         Ok(())
     }
 
+    /// Satisfiers built by us take precedence for the hash, as the sync DBs
+    /// might not have them, or only older ones. Needs still come from the DBs
+    /// as that's where build roots install from
+    fn needed_and_soname_hash(
+        &mut self, db_handle: &DbHandle, built: &[LoadedPackage<'_>]
+    ) -> Result<()>
+    {
+        let mut hash_box = Box::new(xxh3::Xxh3::new());
+        let hash = hash_box.as_mut();
+        for dep_name in self.deps.iter().chain(self.makedeps.iter()) {
+            let synced = db_handle.find_satisfier(dep_name);
+            let dep = match (find_built(built, dep_name), synced.as_ref()) {
+                (Some(dep), _) => dep,
+                (None, Some(dep)) => &**dep,
+                (None, None) => {
+                    log::error!("Warning: dep {} not found", dep_name);
+                    return Err(Error::DependencyMissing(vec![dep_name.clone()]))
+                },
+            };
+            self.needs.push(match &synced {
+                Some(synced) => synced.name().to_string(),
+                None => dep.name().to_string(),
+            });
+            self.satisfiers.push(Satisfier::from_pkg(dep_name, dep, true));
+            update_hash_from_pkg_sonames(hash, dep);
+        }
+        self.hash = hash.finish();
+        Ok(())
+    }
+
     fn needed_and_no_hash(&mut self, db_handle: &DbHandle) -> Result<()> {
         for dep in self.deps.iter().chain(self.makedeps.iter()) {
             let dep = match db_handle.find_satisfier(dep) {
@@ -123,9 +238,11 @@ impl Depends {
         -> Result<()>
     {
         self.needs.clear();
+        self.satisfiers.clear();
         let r = match hash_strategy {
             DepHash::Strict => self.needed_and_strict_hash(db_handle),
             DepHash::Loose => self.needed_and_loose_hash(db_handle),
+            DepHash::Soname => self.needed_and_soname_hash(db_handle, &[]),
             DepHash::None => self.needed_and_no_hash(db_handle),
         };
         self.needs.sort_unstable();
@@ -133,6 +250,78 @@ impl Depends {
         r
     }
 
+    /// Redo the soname hash with packages built by us in this run
+    pub(crate) fn rehash_sonames(
+        &mut self, db_handle: &DbHandle, built: &[LoadedPackage<'_>]
+    ) -> Result<()>
+    {
+        self.needs.clear();
+        self.satisfiers.clear();
+        let r = self.needed_and_soname_hash(db_handle, built);
+        self.needs.sort_unstable();
+        self.needs.dedup();
+        r
+    }
+
+    /// Record the satisfiers that went into the dephash, so later runs could
+    /// tell which deps changed
+    pub(crate) fn write_satisfiers<P: AsRef<Path>>(&self, path: P) -> Result<()>
+    {
+        let mut content = String::new();
+        for satisfier in self.satisfiers.iter() {
+            content.push_str(&format!("{}\t{}\t{}\t{:016x}\n",
+                satisfier.dep, satisfier.name, satisfier.version,
+                satisfier.hash));
+        }
+        let mut file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to create satisfiers record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        if let Err(e) = file.write_all(content.as_bytes()) {
+            log::error!("Failed to write satisfiers record '{}': {}",
+                path.as_ref().display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_satisfiers<P: AsRef<Path>>(path: P)
+        -> Result<Vec<Satisfier>>
+    {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Failed to open satisfiers record '{}': {}",
+                    path.as_ref().display(), e);
+                return Err(e.into())
+            },
+        };
+        let mut satisfiers = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::error!("Failed to read satisfiers record '{}': {}",
+                        path.as_ref().display(), e);
+                    return Err(e.into())
+                },
+            };
+            if line.is_empty() {
+                continue
+            }
+            match Satisfier::from_line(&line) {
+                Some(satisfier) => satisfiers.push(satisfier),
+                None => log::warn!("Ignored malformed satisfier line '{}' in \
+                    '{}'", line, path.as_ref().display()),
+            }
+        }
+        Ok(satisfiers)
+    }
+
     pub(crate) fn update_needed(&mut self, db_handle: &DbHandle)
     {
         self.needs.retain(|pkg|!db_handle.is_installed(pkg));
@@ -20,9 +20,13 @@ use crate::{
         threading::{
             self,
             wait_if_too_busy,
-        }, filesystem::remove_dir_all_try_best, sign::sign_pkgs, pacman::{Depends, DbHandle}, config::DepHash
+        }, filesystem::remove_dir_all_try_best, sign::sign_pkgs, pacman::{Depends, DbHandle}, config::{DepHash, Retention}
     };
 use git2::Oid;
+use nix::fcntl::{
+        renameat2,
+        RenameFlags,
+    };
 use std::{
         collections::HashMap,
         ffi::OsString,
@@ -47,11 +51,18 @@ use std::{
         },
         thread,
         iter::zip,
+        time::{
+            Duration,
+            SystemTime,
+        },
     };
 use xxhash_rust::xxh3::xxh3_64;
 // use super::{depend::Depends, DepHash};
 // use super::depend::DbHandle;
 // mod parse;
+mod history;
+pub(crate) mod pin;
+mod plan;
 
 
 #[derive(Clone)]
@@ -73,12 +84,15 @@ pub(crate) struct PKGBUILD {
     names: Vec<String>,
     pub(crate) need_build: bool,
     pub(crate) pkgid: String,
+    pinned: Option<String>,
+    rebuild: bool,
     pkgdir: PathBuf,
     pkgver: Pkgver,
     provides: Vec<String>,
     sources: Vec<source::Source>,
     subtree: Option<PathBuf>,
     url: String,
+    validpgpkeys: Vec<String>,
 }
 
 impl source::MapByDomain for PKGBUILD {
@@ -172,6 +186,7 @@ impl PKGBUILD {
                 },
                 needs: vec![],
                 hash: 0,
+                satisfiers: vec![],
             },
             extracted: false,
             git: git_parent.join(
@@ -189,6 +204,8 @@ impl PKGBUILD {
             names: vec![],
             need_build: false,
             pkgid: String::new(),
+            pinned: None,
+            rebuild: false,
             pkgdir: PathBuf::from("pkgs"),
             pkgver: Pkgver::Plain,
             provides: vec![],
@@ -209,6 +226,7 @@ impl PKGBUILD {
                 None => None,
             },
             url,
+            validpgpkeys: vec![],
         }
     }
     // If healthy, return the latest commit id
@@ -302,7 +320,7 @@ impl PKGBUILD {
     }
 
     fn get_sources_file<P: AsRef<Path>> (pkgbuild_file: P)
-        -> Result<Vec<source::Source>>
+        -> Result<(Vec<source::Source>, Vec<String>)>
     {
         source::get_sources(pkgbuild_file)
     }
@@ -310,8 +328,9 @@ impl PKGBUILD {
     fn get_sources<P: AsRef<Path>> (&mut self, dir: P) -> Result<()> {
         let pkgbuild_file = dir.as_ref().join(&self.base);
         match Self::get_sources_file(&pkgbuild_file) {
-            Ok(sources) => {
+            Ok((sources, validpgpkeys)) => {
                 self.sources = sources;
+                self.validpgpkeys = validpgpkeys;
                 Ok(())
             },
             Err(_) => Err(Error::BrokenPKGBUILDs(vec![self.base.clone()])),
@@ -349,13 +368,15 @@ impl PKGBUILD {
                 return Err(e.into())
             },
         };
-        match actual_identity.set_root_drop_command(
-            Command::new("/bin/bash")
-                .arg0(&arg0)
-                .arg("-ec")
-                .arg(SCRIPT)
-                .arg("Source extractor")
-                .arg(&pkgbuild_dir))
+        let mut command = Command::new("/bin/bash");
+        command
+            .arg0(&arg0)
+            .arg("-ec")
+            .arg(SCRIPT)
+            .arg("Source extractor")
+            .arg(&pkgbuild_dir);
+        git::set_command_config(&mut command);
+        match actual_identity.set_root_drop_command(&mut command)
                 .stdout(dup_file)
                 .stderr(log_file.file)
             .spawn()
@@ -380,11 +401,22 @@ impl PKGBUILD {
             pkgid.push('-');
             pkgid.push_str(&pkgver);
         }
-        self.pkgdir.push(&pkgid);
+        self.pkgdir = PathBuf::from("pkgs").join(&pkgid);
         self.pkgid = pkgid;
         log::info!("PKGBUILD '{}' pkgid is '{}'", self.base, self.pkgid);
     }
 
+    fn satisfiers_record(&self) -> PathBuf {
+        PathBuf::from("state/depends").join(&self.pkgid)
+    }
+
+    fn write_satisfiers_record(&self) {
+        if let Err(e) = self.depends.write_satisfiers(self.satisfiers_record()) {
+            log::warn!("Failed to record dep satisfiers for '{}': {}",
+                self.pkgid, e)
+        }
+    }
+
     pub(crate) fn get_temp_pkgdir(&self) -> Result<PathBuf> {
         let mut temp_name = self.pkgid.clone();
         temp_name.push_str(".temp");
@@ -426,6 +458,7 @@ impl PKGBUILD {
             .arg("--ignorearch")
             .arg("--nosign")
             .env("PKGDEST", &pkgdest);
+        git::set_command_config(&mut command);
         actual_identity.set_root_chroot_drop_command(&mut command, chroot);
         command.env_remove("PATH");
         Ok(command)
@@ -470,20 +503,32 @@ impl PKGBUILD {
         -> Result<()>
     {
         log::info!("Finishing building '{}'", &self.pkgid);
-        if self.pkgdir.exists() {
-            if let Err(e) = remove_dir_all(&self.pkgdir) {
-                log::error!("Failed to remove existing pkgdir: {}", e);
-                return Err(e.into())
-            }
-        }
         if ! sign.is_empty() {
             sign_pkgs(actual_identity, temp_pkgdir, sign)?;
         }
-        if let Err(e) = rename(&temp_pkgdir, &self.pkgdir) {
+        if self.pkgdir.exists() {
+            // Swap the old build out atomically, it's only removed after the
+            // new build is in place
+            if let Err(e) = renameat2(None, temp_pkgdir, None, &self.pkgdir,
+                RenameFlags::RENAME_EXCHANGE)
+            {
+                log::error!("Failed to exchange temp pkgdir '{}' with existing \
+                    pkgdir '{}': {}", temp_pkgdir.display(),
+                    self.pkgdir.display(), e);
+                return Err(e.into())
+            }
+            log::info!("Replaced existing pkgdir '{}'", self.pkgdir.display());
+            if let Err(e) = remove_dir_all(temp_pkgdir) {
+                log::error!("Failed to remove replaced pkgdir '{}': {}",
+                    temp_pkgdir.display(), e);
+                return Err(e.into())
+            }
+        } else if let Err(e) = rename(&temp_pkgdir, &self.pkgdir) {
             log::error!("Failed to rename temp pkgdir '{}' to persistent pkgdir \
                 '{}': {}", temp_pkgdir.display(), self.pkgdir.display(), e);
             return Err(e.into())
         }
+        self.write_satisfiers_record();
         self.link_pkgs()?;
         log::info!("Finished building '{}'", &self.pkgid);
         Ok(())
@@ -570,12 +615,54 @@ impl PKGBUILDs {
         Ok(Self(pkgbuilds))
     }
 
-    fn sync(&self, hold: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>, terminal: bool)
-        -> Result<()>
+    pub(crate) fn mark_rebuild(&mut self, rebuild: &[String], all: bool) {
+        for name in rebuild.iter() {
+            if ! self.0.iter().any(|pkgbuild| &pkgbuild.base == name) {
+                log::warn!("PKGBUILD '{}' to rebuild is not defined in config",
+                    name)
+            }
+        }
+        for pkgbuild in self.0.iter_mut() {
+            if all || rebuild.contains(&pkgbuild.base) {
+                pkgbuild.rebuild = true
+            }
+        }
+    }
+
+    /// Pins from the pin applet take precedence over those from config
+    pub(crate) fn mark_pins(&mut self, pins: &HashMap<String, String>) {
+        for name in pins.keys() {
+            if ! self.0.iter().any(|pkgbuild| &pkgbuild.base == name) {
+                log::warn!("PKGBUILD '{}' to pin is not defined in config",
+                    name)
+            }
+        }
+        for pkgbuild in self.0.iter_mut() {
+            let pkgid = match pin::read(&pkgbuild.base) {
+                Some(pkgid) => pkgid,
+                None => match pins.get(&pkgbuild.base) {
+                    Some(pkgid) => pkgid.clone(),
+                    None => continue,
+                },
+            };
+            if ! PathBuf::from("pkgs").join(&pkgid).is_dir() {
+                log::warn!("Pinned build '{}' of '{}' does not exist, \
+                    ignoring the pin", pkgid, pkgbuild.base);
+                continue
+            }
+            log::info!("'{}' is pinned to '{}'", pkgbuild.base, pkgid);
+            pkgbuild.pinned = Some(pkgid)
+        }
+    }
+
+    fn sync(&self, hold: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>,
+        rules: &git::GitRules, auth: &source::HttpAuth,
+        limits: &source::DomainLimits, terminal: bool
+    ) -> Result<()>
     {
         let map =
-            PKGBUILD::map_by_domain(&self.0);
-        let repos_map =
+            PKGBUILD::map_by_domain(&self.0)?;
+        let mut repos_map =
             match git::ToReposMap::to_repos_map(
                 map, "sources/PKGBUILD", gmr)
         {
@@ -585,7 +672,8 @@ impl PKGBUILDs {
                 return Err(e.into())
             },
         };
-        git::Repo::sync_mt(repos_map, hold, proxy, terminal)
+        rules.apply(&mut repos_map);
+        git::Repo::sync_mt(repos_map, hold, proxy, auth, limits, terminal)
     }
 
     fn healthy_set_commit(&mut self) -> Result<()> {
@@ -607,7 +695,9 @@ impl PKGBUILDs {
     pub(crate) fn from_config_healthy(
         config: &HashMap<String, PkgbuildConfig>,
         hold: bool, noclean: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>,
-        home_binds: &Vec<String>, terminal: bool
+        rules: &git::GitRules, auth: &source::HttpAuth,
+        limits: &source::DomainLimits, home_binds: &Vec<String>,
+        terminal: bool
     ) -> Result<Self>
     {
         let mut pkgbuilds = Self::from_config(config, home_binds)?;
@@ -635,7 +725,8 @@ impl PKGBUILDs {
                         source::remove_unused("sources/PKGBUILD", &used))),
         };
         if update_pkg {
-            if let Err(e) = pkgbuilds.sync(hold, proxy, gmr, terminal) {
+            if let Err(e) = pkgbuilds.sync(hold, proxy, gmr, rules, auth, limits,
+                terminal) {
                 log::error!("Failed to sync PKGBUILDs: {}", e);
                 return Err(e)
             }
@@ -651,6 +742,21 @@ impl PKGBUILDs {
         Ok(pkgbuilds)
     }
 
+    /// PKGBUILDs as already cached, without syncing, for a plan that must not
+    /// change anything
+    pub(crate) fn from_config_cached(
+        config: &HashMap<String, PkgbuildConfig>, home_binds: &Vec<String>
+    ) -> Result<Self>
+    {
+        let mut pkgbuilds = Self::from_config(config, home_binds)?;
+        if let Err(e) = pkgbuilds.healthy_set_commit() {
+            log::error!("PKGBUILDs not all cached and healthy, run once \
+                without plan to sync them: {}", e);
+            return Err(e)
+        }
+        Ok(pkgbuilds)
+    }
+
     fn dump<P: AsRef<Path>> (&self, dir: P) -> Result<()> {
         let dir = dir.as_ref();
         let mut r = Ok(());
@@ -754,7 +860,7 @@ impl PKGBUILDs {
     }
 
     fn get_all_sources<P: AsRef<Path>> (&mut self, dir: P)
-      -> Result<(Vec<source::Source>, Vec<source::Source>, Vec<source::Source>)>
+      -> Result<source::UniqueSources>
     {
         let mut sources_non_unique = vec![];
         for pkgbuild in self.0.iter_mut() {
@@ -771,6 +877,60 @@ impl PKGBUILDs {
         source::unique_sources(&sources_non_unique)
     }
 
+    /// Verify detached signatures of PKGBUILDs declaring validpgpkeys
+    fn verify_signatures(
+        &self, actual_identity: &IdentityActual, pgp_keyring: &str
+    ) -> Result<()>
+    {
+        if self.0.iter().all(|pkgbuild| pkgbuild.validpgpkeys.is_empty()) {
+            return Ok(())
+        }
+        source::pgp::prepare_keyring(pgp_keyring, actual_identity)?;
+        let mut broken = vec![];
+        for pkgbuild in self.0.iter() {
+            if pkgbuild.validpgpkeys.is_empty() {
+                continue
+            }
+            if let Err(Error::BrokenPKGBUILDs(mut pkgbuilds)) =
+                source::pgp::verify_sources(&pkgbuild.base, &pkgbuild.sources,
+                    &pkgbuild.validpgpkeys, actual_identity)
+            {
+                broken.append(&mut pkgbuilds)
+            }
+        }
+        if broken.is_empty() {
+            Ok(())
+        } else {
+            log::error!("Bad signatures in PKGBUILDs: {:?}", broken);
+            Err(Error::BrokenPKGBUILDs(broken))
+        }
+    }
+
+    /// Resolve fragments of git sources against their mirrors, return the
+    /// URLs and commits of them
+    fn resolve_fragments(&self, settings: &source::CacheSettings)
+        -> Result<Vec<(String, Oid)>>
+    {
+        let mut commits = vec![];
+        let mut broken = vec![];
+        for pkgbuild in self.0.iter() {
+            match git::resolve_fragments(
+                &pkgbuild.base, &pkgbuild.sources, settings)
+            {
+                Ok(mut resolved) => commits.append(&mut resolved),
+                Err(Error::BrokenPKGBUILDs(mut pkgbuilds)) =>
+                    broken.append(&mut pkgbuilds),
+                Err(e) => return Err(e),
+            }
+        }
+        if broken.is_empty() {
+            Ok(commits)
+        } else {
+            log::error!("Unresolvable git refs in PKGBUILDs: {:?}", broken);
+            Err(Error::BrokenPKGBUILDs(broken))
+        }
+    }
+
     fn filter_with_pkgver_func<P: AsRef<Path>>(
         &mut self, actual_identity: &IdentityActual, dir: P
     ) -> Result<Vec<&mut PKGBUILD>>
@@ -973,7 +1133,90 @@ impl PKGBUILDs {
         }
     }
 
-    fn check_if_need_build(&mut self)
+    /// Indices of PKGBUILDs in each dep layer, in build order
+    pub(crate) fn layers(&self) -> Result<Vec<Vec<usize>>> {
+        Ok(crate::pacman::split_pkgbuilds(self)?.iter().map(|layer|
+            layer.iter().filter_map(|pkgbuild| self.0.iter().position(
+                |other| std::ptr::eq(*pkgbuild, other))).collect()
+        ).collect())
+    }
+
+    /// Redo the soname dephash of PKGBUILDs in the layer against packages we
+    /// built for the PKGBUILDs they want, as the sync DBs might not have them
+    /// or only older ones, then their pkgids and whether they need build
+    pub(crate) fn rehash_sonames_layer(
+        &mut self, layer: &[usize], db_handle: &DbHandle, record: bool
+    ) -> Result<()>
+    {
+        for id in layer.iter() {
+            let built: Vec<_> = self.0.iter().filter(|other|
+                self.0[*id].wants(other).is_some()
+            ).flat_map(|other| db_handle.load_pkgs(&match &other.pinned {
+                Some(pinned) => PathBuf::from("pkgs").join(pinned),
+                None => other.pkgdir.clone(),
+            })).collect();
+            if built.is_empty() {
+                continue
+            }
+            let pkgbuild = &mut self.0[*id];
+            pkgbuild.depends.rehash_sonames(db_handle, &built)?;
+            pkgbuild.depends.update_needed(db_handle);
+            let pkgid = pkgbuild.pkgid.clone();
+            pkgbuild.fill_id_dir(&DepHash::Soname);
+            if pkgbuild.pkgid == pkgid {
+                continue
+            }
+            let built = pkgbuild.pkgdir.read_dir()
+                .is_ok_and(|mut dir| dir.next().is_some());
+            pkgbuild.need_build = ! built || pkgbuild.rebuild;
+            log::info!("PKGBUILD '{}' pkgid changed to '{}' with sonames of \
+                our packages, need build: {}",
+                pkgbuild.base, pkgbuild.pkgid, pkgbuild.need_build);
+            if ! pkgbuild.need_build {
+                if record && ! pkgbuild.satisfiers_record().exists() {
+                    pkgbuild.write_satisfiers_record()
+                }
+                if pkgbuild.extracted {
+                    pkgbuild.extracted = false;
+                    remove_dir_all_try_best(&pkgbuild.build)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// With the soname dephash, go through the layers and rehash PKGBUILDs
+    /// against our packages already built. Returns which PKGBUILDs might
+    /// need build, also the ones wanting a PKGBUILD to be built in this run,
+    /// as they're rehashed again after that
+    fn rehash_sonames(&mut self, root: &Path, record: bool)
+        -> Result<Vec<bool>>
+    {
+        let mut maybe: Vec<bool> =
+            self.0.iter().map(|pkgbuild| pkgbuild.need_build).collect();
+        let layers = match self.layers() {
+            Ok(layers) => layers,
+            Err(_) => {
+                log::warn!("Failed to split PKGBUILDs into layers, sonames \
+                    of our own packages are not considered");
+                return Ok(maybe)
+            },
+        };
+        let db_handle = DbHandle::new(root)?;
+        for layer in layers.iter().skip(1) {
+            self.rehash_sonames_layer(layer, &db_handle, record)?;
+            for id in layer.iter() {
+                maybe[*id] = self.0[*id].need_build ||
+                    self.0.iter().enumerate().any(|(other_id, other)|
+                        maybe[other_id] && self.0[*id].wants(other).is_some())
+            }
+        }
+        Ok(maybe)
+    }
+
+    /// Mark which PKGBUILDs need build, recording the dep satisfiers of the
+    /// ones already built if `record`
+    fn check_if_need_build(&mut self, record: bool)
         -> Result<u32>
     {
         let mut cleaners = vec![];
@@ -986,10 +1229,17 @@ impl PKGBUILDs {
                     built = true;
                 }
             }
-            if built { // Does not need build
+            if built && pkgbuild.rebuild {
+                log::info!("Forced to rebuild already built '{}'",
+                    pkgbuild.pkgdir.display());
+            }
+            if built && ! pkgbuild.rebuild { // Does not need build
                 pkgbuild.need_build = false;
                 log::info!("Skipped already built '{}'",
                     pkgbuild.pkgdir.display());
+                if record && ! pkgbuild.satisfiers_record().exists() {
+                    pkgbuild.write_satisfiers_record()
+                }
                 if pkgbuild.extracted {
                     pkgbuild.extracted = false;
                     let dir = pkgbuild.build.clone();
@@ -1017,19 +1267,42 @@ impl PKGBUILDs {
         r
     }
 
+    /// Resolve deps against the DBs at root and fill the pkgids, then tell
+    /// whether any PKGBUILD needs build, and which might
+    fn resolve_deps(
+        &mut self, actual_identity: &IdentityActual, dir: &Path, root: &Path,
+        dephash_strategy: &DepHash, record: bool
+    ) -> Result<(bool, Vec<bool>)>
+    {
+        self.check_deps(actual_identity, dir, root, dephash_strategy)?;
+        self.fill_all_ids_dirs(dephash_strategy);
+        let mut need_builds = self.check_if_need_build(record)? > 0;
+        let maybe = match dephash_strategy {
+            DepHash::Soname => self.rehash_sonames(root, record)?,
+            _ => self.0.iter().map(|pkgbuild| pkgbuild.need_build).collect(),
+        };
+        need_builds |= maybe.iter().any(|maybe| *maybe);
+        Ok((need_builds, maybe))
+    }
+
+    fn build_under(&mut self, parent: &Path) {
+        for pkgbuild in self.0.iter_mut() {
+            pkgbuild.build = parent.join(&pkgbuild.base)
+        }
+    }
+
     pub(crate) fn prepare_sources(
         &mut self,
-        actual_identity: &IdentityActual,
+        settings: &source::CacheSettings,
         basepkgs: &Vec<String>,
-        holdgit: bool,
-        skipint: bool,
         noclean: bool,
-        proxy: Option<&Proxy>,
-        gmr: Option<&git::Gmr>,
+        pgp_keyring: &str,
         dephash_strategy: &DepHash,
-        terminal: bool
+        plan: bool
     ) -> Result<Option<BaseRoot>>
     {
+        let &source::CacheSettings { actual_identity, holdgit, proxy, gmr,
+            limits, rules, terminal, .. } = settings;
 
         let dir = match tempfile::tempdir() {
             Ok(dir) => dir,
@@ -1039,17 +1312,23 @@ impl PKGBUILDs {
             },
         };
         let cleaner = match
-            PathBuf::from("build").exists()
+            ! plan && PathBuf::from("build").exists()
         {
             true => Some(thread::spawn(|| remove_dir_all_try_best("build"))),
             false => None,
         };
         self.dump(&dir)?;
-        let (netfile_sources, git_sources, _)
+        let (netfile_sources, mut git_sources, vcs_sources, _)
             = self.get_all_sources(&dir)?;
-        source::cache_sources_mt(
-            &netfile_sources, &git_sources, actual_identity,
-            holdgit, skipint, proxy, gmr, terminal)?;
+        // A plan works with the sources as cached and fetches nothing
+        if ! plan {
+            source::cache_sources_mt(
+                &netfile_sources, &git_sources, &vcs_sources, settings)?;
+            let commits = self.resolve_fragments(settings)?;
+            git::cache_submodules(&mut git_sources, commits, holdgit, proxy,
+                gmr, rules, limits, terminal)?;
+            self.verify_signatures(actual_identity, pgp_keyring)?;
+        }
         if let Some(cleaner) = cleaner {
             match cleaner.join() {
                 Ok(r) => if let Err(e) = r {
@@ -1064,20 +1343,37 @@ impl PKGBUILDs {
         }
         let cleaners = match noclean {
             true => None,
-            false => Some(source::cleanup(netfile_sources, git_sources)),
+            false => Some(source::cleanup(
+                netfile_sources, git_sources, vcs_sources)),
         };
+        if plan {
+            // Sources are extracted for pkgver() into a throwaway build dir,
+            // and deps resolved against a copy of the host sync DBs as last
+            // refreshed, leaving build and roots as they are
+            let build = match tempfile::tempdir() {
+                Ok(build) => build,
+                Err(e) => {
+                    log::error!("Failed to create temp build dir: {}", e);
+                    return Err(e.into())
+                },
+            };
+            self.build_under(build.path());
+            self.fill_all_pkgvers(actual_identity, &dir)?;
+            let db_copy = BaseRoot::db_copy()?;
+            let (_, maybe) = self.resolve_deps(actual_identity,
+                dir.as_ref(), db_copy.path(), dephash_strategy, false)?;
+            plan::report(self, &maybe, dephash_strategy)?;
+            return Ok(None)
+        }
         self.fill_all_pkgvers(actual_identity, &dir)?;
         // Use the fresh DBs in target root
         let base_root = BaseRoot::db_only()?;
-        self.check_deps(
-            actual_identity, dir.as_ref(), base_root.path(),
-            dephash_strategy)?;
-        self.fill_all_ids_dirs(dephash_strategy);
-        let need_builds = self.check_if_need_build()? > 0;
+        let (need_builds, maybe) = self.resolve_deps(actual_identity,
+            dir.as_ref(), base_root.path(), dephash_strategy, true)?;
         if need_builds {
             let mut all_deps = vec![];
-            for pkgbuild in self.0.iter() {
-                if ! pkgbuild.need_build {
+            for (pkgbuild, maybe) in zip(self.0.iter(), maybe.iter()) {
+                if ! maybe {
                     continue
                 }
                 for dep in pkgbuild.depends.needs.iter() {
@@ -1092,8 +1388,8 @@ impl PKGBUILDs {
             Depends::cache_raw(&all_deps, base_root.db_path())?;
             base_root.finish(actual_identity, basepkgs)?;
             let db_handle = DbHandle::new(base_root.path())?;
-            for pkgbuild in self.0.iter_mut() {
-                if pkgbuild.need_build {
+            for (pkgbuild, maybe) in zip(self.0.iter_mut(), maybe.iter()) {
+                if *maybe {
                     pkgbuild.depends.update_needed(&db_handle);
                 }
             }
@@ -1111,31 +1407,89 @@ impl PKGBUILDs {
         }
     }
 
-    pub(crate) fn clean_pkgdir(&self) {
+    fn archive_pkgid(pkgid: &str, archive: &Path) -> Result<()> {
+        if let Err(e) = create_dir_all(archive) {
+            log::error!("Failed to create archive dir '{}': {}",
+                archive.display(), e);
+            return Err(e.into())
+        }
+        let from = PathBuf::from("pkgs").join(pkgid);
+        let to = archive.join(pkgid);
+        log::info!("Archiving '{}' to '{}'", from.display(), to.display());
+        if let Err(e) = rename(&from, &to) {
+            log::error!("Failed to archive '{}' to '{}': {}",
+                from.display(), to.display(), e);
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clean_pkgdir(&self, retention: &Retention) {
         let mut used: Vec<String> = self.0.iter().map(
             |pkgbuild| pkgbuild.pkgid.clone()).collect();
+        used.extend(self.0.iter().filter_map(
+            |pkgbuild| pkgbuild.pinned.clone()));
+        let keep_age = Duration::from_secs(retention.keep_days * 86400);
+        let now = SystemTime::now();
+        let archive = PathBuf::from(&retention.archive);
+        let mut kept: HashMap<&str, usize> = HashMap::new();
+        for built in history::list_built() {
+            if used.contains(&built.pkgid) {
+                continue
+            }
+            if let Some(pkgbuild) = self.0.iter().find(
+                |pkgbuild| built.of_base(&pkgbuild.base))
+            {
+                let count = kept.entry(&pkgbuild.base).or_default();
+                if *count < retention.keep_last {
+                    *count += 1;
+                    used.push(built.pkgid);
+                    continue
+                }
+            }
+            if let Ok(age) = now.duration_since(built.time) {
+                if age < keep_age {
+                    used.push(built.pkgid);
+                    continue
+                }
+            }
+            if ! retention.archive.is_empty() &&
+                Self::archive_pkgid(&built.pkgid, &archive).is_err()
+            {
+                used.push(built.pkgid)
+            }
+        }
         used.push(String::from("updated"));
         used.push(String::from("latest"));
         used.sort_unstable();
         source::remove_unused("pkgs", &used);
+        source::remove_unused("state/depends", &used);
+        let mut bases: Vec<String> = self.0.iter().map(
+            |pkgbuild| pkgbuild.base.clone()).collect();
+        bases.sort_unstable();
+        source::remove_unused("state/pins", &bases);
     }
 
     pub(crate) fn link_pkgs(&self) {
         let rel = PathBuf::from("..");
         let latest = PathBuf::from("pkgs/latest");
         for pkgbuild in self.0.iter() {
-            if ! pkgbuild.pkgdir.exists() {
+            let (pkgid, pkgdir) = match &pkgbuild.pinned {
+                Some(pinned) => (pinned, PathBuf::from("pkgs").join(pinned)),
+                None => (&pkgbuild.pkgid, pkgbuild.pkgdir.clone()),
+            };
+            if ! pkgdir.exists() {
                 continue;
             }
-            let dirent = match pkgbuild.pkgdir.read_dir() {
+            let dirent = match pkgdir.read_dir() {
                 Ok(dirent) => dirent,
                 Err(e) => {
                     log::error!("Failed to read dir '{}': {}",
-                        pkgbuild.pkgdir.display(), e);
+                        pkgdir.display(), e);
                     continue
                 },
             };
-            let rel = rel.join(&pkgbuild.pkgid);
+            let rel = rel.join(pkgid);
             for entry in dirent {
                 if let Ok(entry) = entry {
                     let original = rel.join(entry.file_name());
@@ -0,0 +1,93 @@
+// Historical builds under pkgs/
+
+use std::{
+        fs::read_dir,
+        time::SystemTime,
+    };
+
+/// The components of a pkgid, as generated by `PKGBUILD::fill_id_dir()`
+pub(super) struct PkgidParts<'a> {
+    pub(super) commit: &'a str,
+    pub(super) dephash: Option<&'a str>,
+    pub(super) pkgver: Option<&'a str>,
+}
+
+fn is_hex(part: &str) -> bool {
+    part.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+impl<'a> PkgidParts<'a> {
+    fn parse(base: &str, pkgid: &'a str, with_dephash: bool) -> Option<Self> {
+        let remaining = pkgid.strip_prefix(base)?.strip_prefix('-')?;
+        let commit = remaining.get(0..40)?;
+        if ! is_hex(commit) {
+            return None
+        }
+        let mut remaining = &remaining[40..];
+        let mut dephash = None;
+        if with_dephash {
+            remaining = remaining.strip_prefix('-')?;
+            let hash = remaining.get(0..16)?;
+            if ! is_hex(hash) {
+                return None
+            }
+            dephash = Some(hash);
+            remaining = &remaining[16..];
+        }
+        let pkgver = if remaining.is_empty() {
+            None
+        } else {
+            Some(remaining.strip_prefix('-')?)
+        };
+        Some(Self { commit, dephash, pkgver })
+    }
+
+    /// Parse with the preferred dephash presence first, then the other, as
+    /// the strategy could be changed between runs
+    pub(super) fn parse_any(base: &str, pkgid: &'a str, with_dephash: bool)
+        -> Option<Self>
+    {
+        Self::parse(base, pkgid, with_dephash).or_else(||
+            Self::parse(base, pkgid, ! with_dephash))
+    }
+}
+
+pub(super) struct Built {
+    pub(super) pkgid: String,
+    pub(super) time: SystemTime,
+}
+
+impl Built {
+    pub(super) fn of_base(&self, base: &str) -> bool {
+        PkgidParts::parse_any(base, &self.pkgid, true).is_some()
+    }
+}
+
+/// All finished pkgdirs under `pkgs/`, newest first
+pub(super) fn list_built() -> Vec<Built> {
+    let readdir = match read_dir("pkgs") {
+        Ok(readdir) => readdir,
+        Err(e) => {
+            log::error!("Failed to read pkgs dir: {}", e);
+            return vec![]
+        },
+    };
+    let mut builts = vec![];
+    for entry in readdir.flatten() {
+        let pkgid = entry.file_name().to_string_lossy().into_owned();
+        if pkgid == "latest" || pkgid == "updated" || pkgid.ends_with(".temp") {
+            continue
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if ! metadata.is_dir() {
+            continue
+        }
+        let time = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        builts.push(Built { pkgid, time })
+    }
+    builts.sort_unstable_by_key(|built| std::cmp::Reverse(built.time));
+    builts
+}
@@ -0,0 +1,122 @@
+// Pins of pkgbases to specific builds, stored as state/pins/<pkgbase>
+
+use std::{
+        fs::{
+            create_dir_all,
+            read_dir,
+            read_to_string,
+            remove_file,
+            write,
+        },
+        io::ErrorKind,
+        path::PathBuf,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        pkgbuild::history::list_built,
+    };
+
+fn record(pkgbase: &str) -> PathBuf {
+    PathBuf::from("state/pins").join(pkgbase)
+}
+
+/// The pkgid the pkgbase was pinned to by the pin applet, if any
+pub(super) fn read(pkgbase: &str) -> Option<String> {
+    let record = record(pkgbase);
+    match read_to_string(&record) {
+        Ok(pkgid) => {
+            let pkgid = pkgid.trim();
+            if pkgid.is_empty() {
+                None
+            } else {
+                Some(pkgid.to_string())
+            }
+        },
+        Err(e) => {
+            if e.kind() != ErrorKind::NotFound {
+                log::warn!("Failed to read pin record '{}': {}",
+                    record.display(), e)
+            }
+            None
+        },
+    }
+}
+
+pub(crate) fn set(pkgbase: &str, pkgid: &str) -> Result<()> {
+    if pkgbase.contains('/') || pkgid.contains('/') {
+        log::error!("Pkgbase and pkgid to pin must not contain '/'");
+        return Err(Error::InvalidArgument)
+    }
+    if ! list_built().iter().any(|built|
+        built.pkgid == pkgid && built.of_base(pkgbase))
+    {
+        log::error!("Pkgid '{}' is not a build of '{}' under pkgs",
+            pkgid, pkgbase);
+        return Err(Error::InvalidArgument)
+    }
+    if let Err(e) = create_dir_all("state/pins") {
+        log::error!("Failed to create pins dir: {}", e);
+        return Err(e.into())
+    }
+    let record = record(pkgbase);
+    if let Err(e) = write(&record, format!("{}\n", pkgid)) {
+        log::error!("Failed to write pin record '{}': {}",
+            record.display(), e);
+        return Err(e.into())
+    }
+    log::info!("Pinned '{}' to '{}'", pkgbase, pkgid);
+    Ok(())
+}
+
+pub(crate) fn unset(pkgbase: &str) -> Result<()> {
+    let record = record(pkgbase);
+    match remove_file(&record) {
+        Ok(_) => {
+            log::info!("Unpinned '{}'", pkgbase);
+            Ok(())
+        },
+        Err(e) => {
+            if e.kind() == ErrorKind::NotFound {
+                log::warn!("'{}' was not pinned", pkgbase);
+                return Ok(())
+            }
+            log::error!("Failed to remove pin record '{}': {}",
+                record.display(), e);
+            Err(e.into())
+        },
+    }
+}
+
+/// Print all pins, or the builds available for the pkgbase to pin to
+pub(crate) fn list(pkgbase: Option<&str>) {
+    match pkgbase {
+        Some(pkgbase) => {
+            let pinned = read(pkgbase);
+            for built in list_built().iter().filter(
+                |built| built.of_base(pkgbase))
+            {
+                if pinned.as_ref() == Some(&built.pkgid) {
+                    println!("{} (pinned)", built.pkgid)
+                } else {
+                    println!("{}", built.pkgid)
+                }
+            }
+        },
+        None => {
+            let readdir = match read_dir("state/pins") {
+                Ok(readdir) => readdir,
+                Err(_) => return,
+            };
+            for entry in readdir.flatten() {
+                let pkgbase = entry.file_name().to_string_lossy().into_owned();
+                if let Some(pkgid) = read(&pkgbase) {
+                    println!("{}: {}", pkgbase, pkgid)
+                }
+            }
+        },
+    }
+}
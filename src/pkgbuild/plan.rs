@@ -0,0 +1,292 @@
+// Dry-run plan, explaining what would be built and why
+
+use std::{
+        io::Write,
+        path::PathBuf,
+    };
+
+use serde::Serialize;
+
+use crate::{
+        config::DepHash,
+        error::{
+            Error,
+            Result
+        },
+        logfile::{
+            LogFile,
+            LogType,
+        },
+        pacman::{
+            Depends,
+            Satisfier,
+        },
+        pkgbuild::{
+            history::{
+                list_built,
+                PkgidParts,
+            },
+            PKGBUILD,
+            PKGBUILDs,
+            Pkgver,
+        },
+    };
+
+#[derive(Serialize)]
+struct DepChange {
+    dep: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+enum Reason {
+    UpToDate,
+    RehashedAfter {
+        deps: Vec<String>,
+    },
+    NoPkgdir,
+    Rebuild,
+    CommitChanged {
+        old: String,
+        new: String,
+    },
+    DephashChanged {
+        old: String,
+        new: String,
+        deps: Vec<DepChange>,
+    },
+    PkgverChanged {
+        old: String,
+        new: String,
+    },
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UpToDate => write!(f, "up to date"),
+            Self::RehashedAfter { deps } => write!(f,
+                "rehashed after {} {} built", deps.join(", "),
+                if deps.len() > 1 { "are" } else { "is" }),
+            Self::NoPkgdir => write!(f, "no pkgdir for this pkgid"),
+            Self::Rebuild => write!(f, "forced rebuild"),
+            Self::CommitChanged { old, new } =>
+                write!(f, "commit {} -> {}", old, new),
+            Self::DephashChanged { old, new, deps } => {
+                write!(f, "dephash {} -> {}", old, new)?;
+                if deps.is_empty() {
+                    return Ok(())
+                }
+                write!(f, " (")?;
+                for (id, change) in deps.iter().enumerate() {
+                    if id > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {} -> {}", change.dep,
+                        change.old.as_deref().unwrap_or("none"),
+                        change.new.as_deref().unwrap_or("none"))?;
+                }
+                write!(f, ")")
+            },
+            Self::PkgverChanged { old, new } =>
+                write!(f, "pkgver {} -> {}", old, new),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    pkgbase: &'a str,
+    pkgid: &'a str,
+    previous: Option<String>,
+    build: bool,
+    maybe: bool, // might build after rehashed against what we build first
+    reasons: Vec<Reason>,
+}
+
+/// Find the latest pkgid in `pkgs/` built from the same PKGBUILD
+fn find_previous(pkgbuild: &PKGBUILD) -> Option<String> {
+    list_built().into_iter().find(|built|
+        built.pkgid != pkgbuild.pkgid && built.of_base(&pkgbuild.base)
+    ).map(|built| built.pkgid)
+}
+
+fn dep_changes(current: &[Satisfier], previous: &[Satisfier])
+    -> Vec<DepChange>
+{
+    let mut changes = vec![];
+    for satisfier in current.iter() {
+        match previous.iter().find(|old| old.dep == satisfier.dep) {
+            Some(old) => if old.hash != satisfier.hash {
+                changes.push(DepChange {
+                    dep: satisfier.dep.clone(),
+                    old: Some(old.to_string()),
+                    new: Some(satisfier.to_string()),
+                })
+            },
+            None => changes.push(DepChange {
+                dep: satisfier.dep.clone(),
+                old: None,
+                new: Some(satisfier.to_string()),
+            }),
+        }
+    }
+    for old in previous.iter() {
+        if ! current.iter().any(|satisfier| satisfier.dep == old.dep) {
+            changes.push(DepChange {
+                dep: old.dep.clone(),
+                old: Some(old.to_string()),
+                new: None,
+            })
+        }
+    }
+    changes
+}
+
+fn explain(
+    pkgbuild: &PKGBUILD, previous: Option<&str>, with_dephash: bool,
+    rehash_after: Vec<String>
+) -> Vec<Reason>
+{
+    if ! pkgbuild.need_build {
+        if rehash_after.is_empty() {
+            return vec![Reason::UpToDate]
+        }
+        return vec![Reason::RehashedAfter { deps: rehash_after }]
+    }
+    if pkgbuild.rebuild && pkgbuild.pkgdir.exists() {
+        return vec![Reason::Rebuild]
+    }
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return vec![Reason::NoPkgdir],
+    };
+    let old = match PkgidParts::parse_any(
+        &pkgbuild.base, previous, with_dephash)
+    {
+        Some(old) => old,
+        None => return vec![Reason::NoPkgdir],
+    };
+    let commit = pkgbuild.commit.to_string();
+    let mut reasons = vec![];
+    if old.commit != commit {
+        reasons.push(Reason::CommitChanged {
+            old: old.commit.to_string(),
+            new: commit,
+        })
+    }
+    if with_dephash {
+        let dephash = format!("{:016x}", pkgbuild.depends.hash);
+        if old.dephash != Some(dephash.as_str()) {
+            let record = PathBuf::from("state/depends").join(previous);
+            let deps = match Depends::read_satisfiers(&record) {
+                Ok(satisfiers) =>
+                    dep_changes(&pkgbuild.depends.satisfiers, &satisfiers),
+                Err(_) => vec![],
+            };
+            reasons.push(Reason::DephashChanged {
+                old: old.dephash.unwrap_or("none").to_string(),
+                new: dephash,
+                deps,
+            })
+        }
+    }
+    let pkgver = match &pkgbuild.pkgver {
+        Pkgver::Plain => None,
+        Pkgver::Func { pkgver } => Some(pkgver.as_str()),
+    };
+    if old.pkgver != pkgver {
+        reasons.push(Reason::PkgverChanged {
+            old: old.pkgver.unwrap_or("none").to_string(),
+            new: pkgver.unwrap_or("none").to_string(),
+        })
+    }
+    if reasons.is_empty() {
+        reasons.push(Reason::NoPkgdir)
+    }
+    reasons
+}
+
+fn print_table(entries: &[Entry]) {
+    let mut width_base = "PKGBASE".len();
+    for entry in entries.iter() {
+        if entry.pkgbase.len() > width_base {
+            width_base = entry.pkgbase.len()
+        }
+    }
+    println!("{:<width$}  {:<5}  REASON", "PKGBASE", "BUILD", width = width_base);
+    for entry in entries.iter() {
+        let build = match (entry.build, entry.maybe) {
+            (true, _) => "yes",
+            (false, true) => "maybe",
+            (false, false) => "no",
+        };
+        for (id, reason) in entry.reasons.iter().enumerate() {
+            if id == 0 {
+                println!("{:<width$}  {:<5}  {}",
+                    entry.pkgbase, build, reason, width = width_base);
+            } else {
+                println!("{:<width$}  {:<5}  {}", "", "", reason,
+                    width = width_base);
+            }
+        }
+    }
+}
+
+fn write_json(entries: &[Entry]) -> Result<()> {
+    let json = match serde_json::to_string_pretty(entries) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize build plan: {}", e);
+            return Err(Error::ImpossibleLogic)
+        },
+    };
+    let mut log_file = LogFile::new_with_ext(
+        LogType::Plan, "pkgbuilds", "json")?;
+    if let Err(e) = log_file.file.write_all(json.as_bytes()) {
+        log::error!("Failed to write build plan into '{}': {}",
+            log_file.path.display(), e);
+        return Err(e.into())
+    }
+    log::info!("Build plan in JSON was written to '{}'",
+        log_file.path.display());
+    Ok(())
+}
+
+/// Report the plan, `maybe` tells which PKGBUILDs might need build, also the
+/// ones to be rehashed after what they want is built
+pub(super) fn report(
+    pkgbuilds: &PKGBUILDs, maybe: &[bool], dephash_strategy: &DepHash
+) -> Result<()>
+{
+    let with_dephash = ! matches!(dephash_strategy, DepHash::None);
+    let mut entries = vec![];
+    for (pkgbuild, might) in pkgbuilds.0.iter().zip(maybe.iter()) {
+        let previous = if pkgbuild.need_build {
+            find_previous(pkgbuild)
+        } else {
+            None
+        };
+        let rehash_after = match might {
+            true => pkgbuilds.0.iter().zip(maybe.iter())
+                .filter(|(other, other_might)|
+                    **other_might && pkgbuild.wants(other).is_some())
+                .map(|(other, _)| other.base.clone()).collect(),
+            false => vec![],
+        };
+        let reasons = explain(
+            pkgbuild, previous.as_deref(), with_dephash, rehash_after);
+        entries.push(Entry {
+            pkgbase: &pkgbuild.base,
+            pkgid: &pkgbuild.pkgid,
+            previous,
+            build: pkgbuild.need_build,
+            maybe: *might,
+            reasons,
+        })
+    }
+    print_table(&entries);
+    write_json(&entries)
+}
@@ -1,8 +1,10 @@
 use std::{
         ffi::OsStr,
         fs::{
+            copy,
             create_dir,
             create_dir_all,
+            read_dir,
         },
         path::{
             Path,
@@ -118,6 +120,53 @@ impl BaseRoot {
         Ok(root)
     }
 
+    /// A throwaway root with only a copy of the host sync DBs as last
+    /// refreshed, for plans to resolve deps against without touching roots
+    pub(crate) fn db_copy() -> Result<tempfile::TempDir> {
+        let root = match tempfile::tempdir() {
+            Ok(root) => root,
+            Err(e) => {
+                log::error!("Failed to create temp dir for DB copy: {}", e);
+                return Err(e.into())
+            },
+        };
+        let sync = root.path().join("var/lib/pacman/sync");
+        if let Err(e) = create_dir_all(&sync) {
+            log::error!("Failed to create DB copy dir '{}': {}",
+                sync.display(), e);
+            return Err(e.into())
+        }
+        let host = match read_dir("/var/lib/pacman/sync") {
+            Ok(host) => host,
+            Err(e) => {
+                log::error!("Failed to read host sync DBs, refresh them with \
+                    'pacman -Sy' first: {}", e);
+                return Err(e.into())
+            },
+        };
+        for entry in host {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    log::error!("Failed to read host sync DB entry: {}", e);
+                    return Err(e.into())
+                },
+            };
+            let name = match path.file_name() {
+                Some(name) if path.extension() == Some(OsStr::new("db")) =>
+                    name,
+                _ => continue,
+            };
+            if let Err(e) = copy(&path, sync.join(name)) {
+                log::error!("Failed to copy host sync DB '{}': {}",
+                    path.display(), e);
+                return Err(e.into())
+            }
+        }
+        log::info!("Copied host sync DBs to '{}'", root.path().display());
+        Ok(root)
+    }
+
     /// Create a base rootfs containing the minimum packages and user setup
     /// This should not be used directly for building packages
     pub(crate) fn _new<I, S>(actual_identity: &IdentityActual, pkgs: I)
@@ -3,20 +3,31 @@ use std::{
         str::FromStr,
     };
 
-mod aur;
+use crate::error::{
+        Error,
+        Result,
+    };
+
+pub(crate) mod aur;
+mod blob;
 mod cache;
 mod cksums;
 mod clean;
 mod download;
 mod extract;
 pub(crate) mod git;
+mod limit;
 mod protocol;
+mod mirror;
 mod netfile;
 mod parse;
+pub(crate) mod pgp;
 mod proxy;
+pub(crate) mod quarantine;
+mod vcs;
 
 use cksums::{
-    IntegFile,
+    Integ,
     Cksum,
     Md5sum,
     Sha1sum,
@@ -34,15 +45,22 @@ use protocol::{
 
 pub(crate) use parse::{
     get_sources,
-    unique_sources
+    unique_sources,
+    UniqueSources,
 };
 
-pub(crate) use cache::cache_sources_mt;
+pub(crate) use cache::{
+    cache_sources_mt,
+    CacheSettings,
+};
 pub(crate) use clean::{
     cleanup,
     remove_unused,
 };
+pub(crate) use download::HttpAuth;
 pub(crate) use extract::extract;
+pub(crate) use limit::DomainLimits;
+pub(crate) use mirror::NetfileMirrors;
 pub(crate) use proxy::Proxy;
 
 #[derive(Clone)]
@@ -51,6 +69,7 @@ pub(crate) struct Source {
     protocol: Protocol,
     url: String,
     hash_url: u64,
+    fragment: Option<git::Fragment>, // #branch=, #commit= or #tag= of git
     ck: Option<Cksum>,     // 32-bit CRC
     md5: Option<Md5sum>,   // 128-bit MD5
     sha1: Option<Sha1sum>,  // 160-bit SHA-1
@@ -63,27 +82,34 @@ pub(crate) struct Source {
 
 pub(crate) trait MapByDomain {
     fn url(&self) -> &str;
-    fn map_by_domain(sources: &Vec<Self>) -> HashMap<u64, Vec<Self>>
+    /// Group by the hash of the host, IP literals included. A URL without a
+    /// host, e.g. scp-like or local, could not be grouped and is an error
+    fn map_by_domain(sources: &Vec<Self>) -> Result<HashMap<u64, Vec<Self>>>
     where
         Self: Clone + Sized
     {
-        let mut map = HashMap::new();
+        let mut map: HashMap<u64, Vec<Self>> = HashMap::new();
         for source in sources.iter() {
-            let url =
-                url::Url::from_str(source.url())
-                .expect("Failed to parse URL");
-            let domain = xxhash_rust::xxh3::xxh3_64(
-                url.domain().expect("Failed to get domain")
-                .as_bytes());
-            if ! map.contains_key(&domain) {
-                map.insert(domain, vec![]);
-            }
-            let vec = map
-                .get_mut(&domain)
-                .expect("Failed to get vec");
-            vec.push(source.clone());
+            let url = match url::Url::from_str(source.url()) {
+                Ok(url) => url,
+                Err(e) => {
+                    log::error!("Failed to parse URL '{}': {}",
+                        source.url(), e);
+                    return Err(e.into())
+                },
+            };
+            let host = match url.host_str() {
+                Some(host) => host,
+                None => {
+                    log::error!("URL '{}' has no host to group by",
+                        source.url());
+                    return Err(Error::InvalidArgument)
+                },
+            };
+            map.entry(xxhash_rust::xxh3::xxh3_64(host.as_bytes()))
+                .or_default().push(source.clone());
         }
-        map
+        Ok(map)
     }
 }
 
@@ -1,46 +1,135 @@
+// AUR RPC info lookups of PKGBUILD repos, split into batches so the URL stays
+// within the RPC limits. Results are cached in state/aur as lines of
+// '<name> <last modified>', so unchanged repos are still skipped when the RPC
+// is briefly unavailable
+
+use std::{
+        collections::HashMap,
+        fs::{
+            read_to_string,
+            rename,
+            write,
+        },
+    };
+
+use percent_encoding::{
+        AsciiSet,
+        NON_ALPHANUMERIC,
+        utf8_percent_encode,
+    };
 use serde::Deserialize;
 
-use crate::error::{
-        Error,
-        Result
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::{
+            download::get_following,
+            HttpAuth,
+        },
     };
 
-#[derive(Deserialize, Debug)]
+const AUR_MAX_URL_LEN: usize = 4000;
+const CACHE: &str = "state/aur";
+// Pkgnames could also contain '+' and '@', which must be escaped in queries
+const ARG: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.')
+    .remove(b'_');
+
+mod health;
+
+pub(crate) use health::{
+        AUR_RPC,
+        Health,
+    };
+
+#[derive(Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct AurPackage {
     pub(crate) last_modified: i64,
-    pub(crate) name: String
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) package_base: String,
+    #[serde(default)]
+    pub(crate) version: String,
+    #[serde(default)]
+    pub(crate) maintainer: Option<String>, // None if orphaned
+    #[serde(default)]
+    pub(crate) out_of_date: Option<i64>, // when flagged
+    #[serde(default)]
+    pub(crate) num_votes: u64,
 }
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct AurResult {
+    #[serde(default)]
+    pub(crate) error: Option<String>,
     pub(crate) results: Vec<AurPackage>,
 }
 
+/// Info URLs each querying a batch of the packages
+fn batch_urls<I, S>(rpc: &str, pkgs: I) -> Vec<(String, Vec<String>)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>
+{
+    let info = format!("{}/info?", rpc);
+    let mut batches = vec![];
+    let mut url = info.clone();
+    let mut batch = vec![];
+    for pkg in pkgs {
+        let arg = format!("arg%5B%5D={}", // arg[]=
+            utf8_percent_encode(pkg.as_ref(), ARG));
+        if ! batch.is_empty() && url.len() + arg.len() + 1 > AUR_MAX_URL_LEN {
+            batches.push((url, batch));
+            url = info.clone();
+            batch = vec![];
+        }
+        if ! batch.is_empty() {
+            url.push('&')
+        }
+        url.push_str(&arg);
+        batch.push(pkg.as_ref().to_string());
+    }
+    if ! batch.is_empty() {
+        batches.push((url, batch))
+    }
+    batches
+}
+
+fn read_cache() -> HashMap<String, i64> {
+    let content = match read_to_string(CACHE) {
+        Ok(content) => content,
+        Err(_) => return HashMap::new(),
+    };
+    content.lines().filter_map(|line| {
+        let (name, last_modified) = line.split_once(' ')?;
+        Some((name.to_string(), last_modified.parse().ok()?))
+    }).collect()
+}
+
+fn write_cache(cache: &HashMap<String, i64>) {
+    let mut names: Vec<&String> = cache.keys().collect();
+    names.sort_unstable();
+    let mut content = String::new();
+    for name in names {
+        content.push_str(&format!("{} {}\n", name, cache[name]))
+    }
+    let temp = format!("{}.temp", CACHE);
+    if let Err(e) = write(&temp, content).and_then(|_| rename(&temp, CACHE)) {
+        log::warn!("Failed to write AUR results cache '{}': {}", CACHE, e)
+    }
+}
+
 impl AurResult {
-    pub(crate) fn from_pkgs<I, S>(pkgs: I) -> Result<Self>
-    where
-        I: IntoIterator<Item = S>,
-        S: AsRef<str>
-    {
+    fn from_url(agent: &ureq::Agent, url: &str) -> Result<Self> {
         const AUR_MAX_TRIES: usize = 3;
-        let mut url = String::from(
-            "https://aur.archlinux.org/rpc/v5/info?");
-        let mut started = false;
-        for pkg in pkgs {
-            if started {
-                url.push('&')
-            } else {
-                started = true
-            }
-            url.push_str("arg%5B%5D="); // arg[]=
-            url.push_str(pkg.as_ref());
-        }
         let mut last_error = Error::ImpossibleLogic;
         for i in 0..AUR_MAX_TRIES {
             log::info!("Requesting AUR, try {} of {}", i + 1, AUR_MAX_TRIES);
             log::info!("Requesting URL '{}'", url);
-            let response = match ureq::get(&url).call() {
+            let response = match get_following(agent, url, |request| request)
+            {
                 Ok(response) => response,
                 Err(e) => {
                     log::error!("Failed to call AUR: {}", e);
@@ -48,7 +137,11 @@ impl AurResult {
                     continue
                 },
             };
-            match response.into_json() {
+            match response.into_json::<Self>() {
+                Ok(Self { error: Some(e), .. }) => {
+                    log::error!("AUR RPC refused the request: {}", e);
+                    return Err(Error::InvalidArgument)
+                },
                 Ok(result) => return Ok(result),
                 Err(e) => {
                     log::error!("Failed to parse response: {}", e);
@@ -59,4 +152,48 @@ impl AurResult {
         log::error!("Failed to get AUR result after all tries");
         Err(last_error)
     }
-}
\ No newline at end of file
+
+    /// Query the packages in batches, a failed batch falls back to the cached
+    /// results of its packages, so only fails if nothing is known at all
+    pub(crate) fn from_pkgs<I, S>(pkgs: I, auth: &HttpAuth) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>
+    {
+        let agent = auth.agent_builder().build();
+        let mut cache = read_cache();
+        let mut results = vec![];
+        let mut last_error = None;
+        let batches = batch_urls(AUR_RPC, pkgs);
+        // Packages no longer looked up are dropped, so the cache stays small
+        cache.retain(|name, _| batches.iter().any(
+            |(_, batch)| batch.contains(name)));
+        for (url, batch) in batches {
+            match Self::from_url(&agent, &url) {
+                Ok(result) => {
+                    for pkg in result.results.iter() {
+                        cache.insert(pkg.name.clone(), pkg.last_modified);
+                    }
+                    results.extend(result.results)
+                },
+                Err(e) => {
+                    let cached: Vec<AurPackage> = batch.into_iter()
+                        .filter_map(|name| Some(AurPackage {
+                            last_modified: *cache.get(&name)?,
+                            name,
+                            ..Default::default()
+                        })).collect();
+                    log::warn!("Using cached AUR results of {} packages as \
+                        the RPC failed", cached.len());
+                    results.extend(cached);
+                    last_error = Some(e)
+                },
+            }
+        }
+        write_cache(&cache);
+        match last_error {
+            Some(e) if results.is_empty() => Err(e),
+            _ => Ok(Self { error: None, results }),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Health of our AUR PKGBUILDs as the AUR RPC sees them: orphaned ones without
+// a maintainer, ones flagged out-of-date, and vanished ones no longer on AUR.
+// The info RPC only takes pkgnames, so a pkgbase not found by info is looked
+// up by a name search, to not mistake split packages for vanished ones
+
+use std::collections::HashMap;
+
+use percent_encoding::utf8_percent_encode;
+
+use crate::{
+        error::Result,
+        source::HttpAuth,
+    };
+
+use super::{
+        ARG,
+        AurPackage,
+        AurResult,
+        batch_urls,
+    };
+
+pub(crate) const AUR_RPC: &str = "https://aur.archlinux.org/rpc/v5";
+
+#[derive(Default)]
+pub(crate) struct Health {
+    orphaned: Vec<AurPackage>,
+    flagged: Vec<AurPackage>,
+    vanished: Vec<String>,
+    unknown: Vec<String>, // searches refused, e.g. too many results
+}
+
+fn format_date(timestamp: i64) -> String {
+    const DATE_FORMAT: &[time::format_description::FormatItem<'_>] =
+        time::macros::format_description!("[year]-[month]-[day]");
+    time::OffsetDateTime::from_unix_timestamp(timestamp).ok()
+        .and_then(|date| date.format(DATE_FORMAT).ok())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn search_base(agent: &ureq::Agent, rpc: &str, pkgbase: &str)
+    -> Result<Option<AurPackage>>
+{
+    let url = format!("{}/search/{}?by=name", rpc,
+        utf8_percent_encode(pkgbase, ARG));
+    Ok(AurResult::from_url(agent, &url)?.results.into_iter()
+        .find(|pkg| pkg.package_base == pkgbase))
+}
+
+impl Health {
+    /// Look up the pkgbases on the RPC at the prefix, e.g. AUR_RPC, failing if
+    /// any info query failed, as vanished ones could not be told then
+    pub(crate) fn check(rpc: &str, pkgbases: &[String], auth: &HttpAuth)
+        -> Result<Self>
+    {
+        let agent = auth.agent_builder().build();
+        let mut found: HashMap<String, AurPackage> = HashMap::new();
+        for (url, _) in batch_urls(rpc, pkgbases) {
+            for pkg in AurResult::from_url(&agent, &url)?.results {
+                found.insert(pkg.package_base.clone(), pkg);
+            }
+        }
+        let mut health = Self::default();
+        for pkgbase in pkgbases.iter() {
+            let pkg = match found.remove(pkgbase) {
+                Some(pkg) => pkg,
+                None => match search_base(&agent, rpc, pkgbase) {
+                    Ok(Some(pkg)) => pkg,
+                    Ok(None) => {
+                        health.vanished.push(pkgbase.clone());
+                        continue
+                    },
+                    Err(_) => {
+                        health.unknown.push(pkgbase.clone());
+                        continue
+                    },
+                },
+            };
+            if pkg.maintainer.is_none() {
+                health.orphaned.push(pkg.clone())
+            }
+            if pkg.out_of_date.is_some() {
+                health.flagged.push(pkg)
+            }
+        }
+        health.vanished.sort_unstable();
+        health.unknown.sort_unstable();
+        Ok(health)
+    }
+
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.orphaned.is_empty() && self.flagged.is_empty() &&
+            self.vanished.is_empty() && self.unknown.is_empty()
+    }
+
+    pub(crate) fn print(&self) {
+        if self.is_healthy() {
+            println!("All AUR packages are maintained, up to date and present");
+            return
+        }
+        if ! self.orphaned.is_empty() {
+            println!("Orphaned:");
+            for pkg in self.orphaned.iter() {
+                println!("  {} {} ({} votes)",
+                    pkg.package_base, pkg.version, pkg.num_votes)
+            }
+        }
+        if ! self.flagged.is_empty() {
+            println!("Flagged out-of-date:");
+            for pkg in self.flagged.iter() {
+                println!("  {} {} since {}, maintained by {}",
+                    pkg.package_base, pkg.version,
+                    format_date(pkg.out_of_date.unwrap_or_default()),
+                    pkg.maintainer.as_deref().unwrap_or("nobody"))
+            }
+        }
+        if ! self.vanished.is_empty() {
+            println!("Vanished:");
+            for pkgbase in self.vanished.iter() {
+                println!("  {}", pkgbase)
+            }
+        }
+        if ! self.unknown.is_empty() {
+            println!("Could not be checked:");
+            for pkgbase in self.unknown.iter() {
+                println!("  {}", pkgbase)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+            collections::HashMap,
+            io::{
+                BufRead,
+                BufReader,
+                Write,
+            },
+            net::TcpListener,
+            path::Path,
+            thread::spawn,
+        };
+
+    use crate::source::HttpAuth;
+
+    use super::Health;
+
+    const PACKAGES: &str = r#"[
+        {"Name": "orphan", "PackageBase": "orphan", "Version": "1-1",
+            "Maintainer": null, "OutOfDate": null, "LastModified": 1,
+            "NumVotes": 3},
+        {"Name": "flagged", "PackageBase": "flagged", "Version": "2-1",
+            "Maintainer": "someone", "OutOfDate": 1700000000,
+            "LastModified": 2, "NumVotes": 5},
+        {"Name": "healthy", "PackageBase": "healthy", "Version": "3-1",
+            "Maintainer": "someone", "OutOfDate": null, "LastModified": 3,
+            "NumVotes": 7},
+        {"Name": "renamed-split", "PackageBase": "renamed", "Version": "4-1",
+            "Maintainer": "someone", "OutOfDate": null, "LastModified": 4,
+            "NumVotes": 9}
+    ]"#;
+
+    /// Serve canned info and search results like the RPC, one request per
+    /// connection, until the test process exits
+    fn serve() -> String {
+        let packages: Vec<serde_json::Value> =
+            serde_json::from_str(PACKAGES).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut line = request.clone();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            let target = request.split_whitespace().nth(1).unwrap();
+            let field = |pkg: &serde_json::Value, key: &str|
+                pkg[key].as_str().unwrap_or_default().to_string();
+            let results: Vec<&serde_json::Value> =
+                if let Some(query) = target.strip_prefix("/rpc/v5/info?") {
+                    let names: Vec<&str> = query.split('&').filter_map(
+                        |arg| arg.strip_prefix("arg%5B%5D=")).collect();
+                    packages.iter().filter(|pkg|
+                        names.contains(&field(pkg, "Name").as_str())).collect()
+                } else if let Some(search) =
+                    target.strip_prefix("/rpc/v5/search/")
+                {
+                    let term = search.split('?').next().unwrap();
+                    packages.iter().filter(|pkg|
+                        field(pkg, "Name").contains(term)).collect()
+                } else {
+                    vec![]
+                };
+            let body = serde_json::json!({
+                "resultcount": results.len(),
+                "results": results,
+                "type": "multiinfo",
+                "version": 5,
+            }).to_string();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\
+                \r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(), body).unwrap();
+        });
+        format!("http://127.0.0.1:{}/rpc/v5", port)
+    }
+
+    #[test]
+    fn classify() {
+        let rpc = serve();
+        let auth = HttpAuth::new(&HashMap::new(), Path::new("/nonexistent"))
+            .unwrap();
+        let pkgbases: Vec<String> = ["flagged", "gone", "healthy", "orphan",
+            "renamed"].iter().map(|pkgbase| pkgbase.to_string()).collect();
+        let health = Health::check(&rpc, &pkgbases, &auth).unwrap();
+        let names = |pkgs: &[super::AurPackage]| -> Vec<String> {
+            pkgs.iter().map(|pkg| pkg.package_base.clone()).collect()
+        };
+        assert_eq!(names(&health.orphaned), ["orphan"]);
+        assert_eq!(names(&health.flagged), ["flagged"]);
+        // renamed is no pkgname, but found by searching its split package
+        assert_eq!(health.vanished, ["gone"]);
+        assert!(health.unknown.is_empty());
+        assert!(! health.is_healthy());
+    }
+}
@@ -0,0 +1,209 @@
+// Content-addressed store of netfile sources with integrity checksums: every
+// file is kept once as sources/blobs/<sha256>, and for checksums of other
+// algorithms sources/index-<algo>/<sum> records the sha256 of the blob. A
+// declared sha256 is only indexed if a mismatching file was accepted with
+// skipint, so the source finds it again
+
+use std::{
+        collections::HashMap,
+        fs::{
+            read_dir,
+            read_to_string,
+            remove_dir,
+            remove_file,
+            rename,
+            write,
+        },
+        os::unix::fs::MetadataExt,
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::{
+            cksums::{
+                Integ,
+                sha256_string,
+            },
+            download::clone_file,
+        },
+    };
+
+pub(super) const BLOBS: &str = "sources/blobs";
+pub(super) const INDEXED: [&str; 8] =
+    ["ck", "md5", "sha1", "sha224", "sha256", "sha384", "sha512", "b2"];
+
+pub(super) fn blob_path(sha256: &str) -> PathBuf {
+    PathBuf::from(BLOBS).join(sha256)
+}
+
+pub(super) fn index_dir(algo: &str) -> String {
+    format!("sources/index-{}", algo)
+}
+
+fn index_path(integ: &Integ) -> PathBuf {
+    PathBuf::from(index_dir(integ.algo())).join(integ.sum_string())
+}
+
+/// Read the sha256 an index entry points to, without checking the blob
+pub(super) fn read_index(path: &Path) -> Option<String> {
+    let sha256 = read_to_string(path).ok()?;
+    let sha256 = sha256.trim();
+    if sha256.len() == 64 && sha256.bytes().all(|c| c.is_ascii_hexdigit()) {
+        Some(sha256.to_string())
+    } else {
+        None
+    }
+}
+
+/// The sha256 of an existing blob recorded for any of the checksums
+pub(super) fn lookup(integs: &[Integ]) -> Option<String> {
+    for integ in integs.iter() {
+        if let Integ::SHA256 ( _ ) = integ {
+            let sha256 = integ.sum_string();
+            if blob_path(&sha256).exists() {
+                return Some(sha256)
+            }
+        }
+        let sha256 = match read_index(&index_path(integ)) {
+            Some(sha256) => sha256,
+            None => continue,
+        };
+        if blob_path(&sha256).exists() {
+            return Some(sha256)
+        }
+    }
+    None
+}
+
+pub(super) fn verify(path: &Path, integs: &[Integ], skipint: bool) -> bool {
+    if ! path.exists() {
+        log::error!("Blob file '{}' does not exist", path.display());
+        return false
+    }
+    if skipint {
+        log::warn!("Integrity check skipped for '{}'", path.display());
+        return true
+    }
+    integs.iter().all(|integ| integ.verify(path))
+}
+
+/// Point all checksums to the blob, except a sha256 that is the blob itself
+pub(super) fn write_index(integs: &[Integ], sha256: &str) -> Result<()> {
+    for integ in integs.iter() {
+        if let Integ::SHA256 ( _ ) = integ {
+            if integ.sum_string() == sha256 {
+                continue
+            }
+        }
+        let index_path = index_path(integ);
+        if read_index(&index_path).as_deref() == Some(sha256) {
+            continue
+        }
+        if let Err(e) = write(&index_path, format!("{}\n", sha256)) {
+            log::error!("Failed to write index '{}': {}",
+                index_path.display(), e);
+            return Err(Error::IoError(e))
+        }
+    }
+    Ok(())
+}
+
+/// Downloads go here before they're verified and named by their sha256,
+/// named after the first checksum so they could be resumed on the next run
+pub(super) fn temp_path(integs: &[Integ]) -> Result<PathBuf> {
+    match integs.first() {
+        Some(integ) => Ok(PathBuf::from(BLOBS).join(
+            format!("{}-{}.temp", integ.algo(), integ.sum_string()))),
+        None => {
+            log::error!("Temp blob path requested without checksums");
+            Err(Error::ImpossibleLogic)
+        },
+    }
+}
+
+/// Move a verified download into the store and index it
+pub(super) fn store(temp: &Path, integs: &[Integ]) -> Result<String> {
+    let sha256 = sha256_string(temp)?;
+    let blob = blob_path(&sha256);
+    if let Err(e) = rename(temp, &blob) {
+        log::warn!("Failed to move '{}' to '{}': {}, trying to clone",
+            temp.display(), blob.display(), e);
+        clone_file(temp, &blob)?;
+        if let Err(e) = remove_file(temp) {
+            log::error!("Failed to remove temp file '{}': {}",
+                temp.display(), e);
+            return Err(Error::IoError(e))
+        }
+    }
+    write_index(integs, &sha256)?;
+    Ok(sha256)
+}
+
+/// Move files from the old per-algorithm sources/file-<algo> layout, where a
+/// file was hard-linked into the dir of every algorithm it had checksums for,
+/// into the store. Dirs are removed once empty
+pub(super) fn migrate() {
+    // Hard links of the same file only need hashing once
+    let mut hashed: HashMap<(u64, u64), String> = HashMap::new();
+    for algo in ["ck", "md5", "sha1", "sha224", "sha256", "sha384",
+        "sha512", "b2"]
+    {
+        let dir = format!("sources/file-{}", algo);
+        let readdir = match read_dir(&dir) {
+            Ok(readdir) => readdir,
+            Err(_) => continue,
+        };
+        log::info!("Migrating '{}' into blob store", dir);
+        for entry in readdir.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if ! metadata.is_file() || name.ends_with(".temp") {
+                let _ = remove_file(&path);
+                continue
+            }
+            let key = (metadata.dev(), metadata.ino());
+            let sha256 = match hashed.get(&key) {
+                Some(sha256) => sha256.clone(),
+                None => match sha256_string(&path) {
+                    Ok(sha256) => {
+                        hashed.insert(key, sha256.clone());
+                        sha256
+                    },
+                    Err(_) => continue,
+                },
+            };
+            let blob = blob_path(&sha256);
+            let r = if blob.exists() {
+                remove_file(&path)
+            } else {
+                rename(&path, &blob)
+            };
+            if let Err(e) = r {
+                log::warn!("Failed to migrate '{}' to '{}': {}",
+                    path.display(), blob.display(), e);
+                continue
+            }
+            if algo != "sha256" {
+                let index_path = PathBuf::from(index_dir(algo)).join(&name);
+                if let Err(e) = write(&index_path, format!("{}\n", sha256)) {
+                    log::warn!("Failed to write index '{}': {}",
+                        index_path.display(), e);
+                }
+            }
+        }
+        if let Err(e) = remove_dir(&dir) {
+            log::warn!("Failed to remove migrated '{}': {}", dir, e)
+        }
+    }
+}
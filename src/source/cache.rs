@@ -12,12 +12,23 @@ use crate::{
             Result
         },
         source::{
-            cksums::IntegFile,
-            git::ToReposMap,
+            blob,
+            cksums::Integ,
+            download::{
+                HttpAgents,
+                HttpAuth,
+            },
+            git::{
+                GitRules,
+                ToReposMap,
+            },
+            DomainLimits,
             MapByDomain,
+            NetfileMirrors,
             netfile,
             Proxy,
             Source,
+            vcs,
         }
     };
 
@@ -49,52 +60,97 @@ fn get_domain_threads_from_map<'a>(
     }
 }
 
+/// What caching sources works with, shared by all sources
+pub(crate) struct CacheSettings<'a> {
+    pub(crate) actual_identity: &'a crate::identity::IdentityActual,
+    pub(crate) holdgit: bool,
+    pub(crate) skipint: bool,
+    pub(crate) proxy: Option<&'a Proxy>,
+    pub(crate) gmr: Option<&'a super::git::Gmr>,
+    pub(crate) mirrors: &'a NetfileMirrors,
+    pub(crate) auth: &'a HttpAuth,
+    pub(crate) limits: &'a DomainLimits,
+    pub(crate) rules: &'a GitRules,
+    pub(crate) terminal: bool,
+}
+
 // TODO: Use now-being-implemented thread pool
 pub(crate) fn cache_sources_mt(
     netfile_sources: &Vec<Source>,
     git_sources: &Vec<Source>,
-    actual_identity: &crate::identity::IdentityActual,
-    holdgit: bool,
-    skipint: bool,
-    proxy: Option<&Proxy>,
-    gmr: Option<&super::git::Gmr>,
-    terminal: bool
+    vcs_sources: &Vec<Source>,
+    settings: &CacheSettings
 ) -> Result<()>
 {
+    let &CacheSettings { actual_identity, holdgit, skipint, proxy, gmr,
+        mirrors, auth, limits, rules, terminal } = settings;
+    blob::migrate();
     let mut netfile_sources_map =
-        Source::map_by_domain(netfile_sources);
+        Source::map_by_domain(netfile_sources)?;
     let git_sources_map =
-        Source::map_by_domain(git_sources);
+        Source::map_by_domain(git_sources)?;
+    let mut vcs_sources_map =
+        Source::map_by_domain(vcs_sources)?;
     let mut netfile_threads_map = 
         get_domain_threads_map(&netfile_sources_map)?;
+    let mut netfile_agents_map = HashMap::new();
+    for domain in netfile_sources_map.keys() {
+        netfile_agents_map.insert(*domain, HttpAgents::new(
+            proxy, auth, limits)?);
+    }
     let mut git_threads_map =
         get_domain_threads_map(&git_sources_map)?;
+    let mut vcs_threads_map =
+        get_domain_threads_map(&vcs_sources_map)?;
     let mut git_repos_map =
         Source::to_repos_map(git_sources_map, "sources/git", gmr)?;
+    rules.apply(&mut git_repos_map);
+    for git_repos in git_repos_map.values_mut() {
+        for git_repo in git_repos.iter_mut() {
+            git_repo.set_limits(limits)
+        }
+    }
     const MAX_THREADS: usize = 10;
     let mut bad = false;
-    while netfile_sources_map.len() > 0 || git_repos_map.len() > 0 {
+    while netfile_sources_map.len() > 0 || git_repos_map.len() > 0 ||
+        vcs_sources_map.len() > 0
+    {
         for (domain, netfile_sources) in
             netfile_sources_map.iter_mut()
         {
             let netfile_threads = 
                 get_domain_threads_from_map(domain, &mut netfile_threads_map)?;
+            let max_threads = limits.threads(*domain, MAX_THREADS);
             while netfile_sources.len() > 0 &&
-                netfile_threads.len() < MAX_THREADS
+                netfile_threads.len() < max_threads
             {
                 let netfile_source = netfile_sources
                     .pop()
                     .expect("Failed to get source from sources vec");
-                let integ_files
-                    = IntegFile::vec_from_source(&netfile_source);
+                let agents = match netfile_agents_map.get(domain) {
+                    Some(agents) => agents.clone(),
+                    None => {
+                        log::error!("Domain {:x} has no HTTP agents", domain);
+                        return Err(Error::ImpossibleLogic)
+                    },
+                };
+                let integs = Integ::vec_from_source(&netfile_source);
                 let proxy_thread = proxy
                     .map(|proxy|proxy.to_owned());
                 let actual_identity_thread = actual_identity.clone();
+                let mirrors_thread = mirrors.clone();
                 let netfile_thread = thread::spawn(
                 move ||{
-                    netfile::cache_source(&netfile_source, &integ_files,
-                         &actual_identity_thread, skipint,
-                         proxy_thread.as_ref())
+                    if integs.is_empty() {
+                        netfile::cache_url_source(&netfile_source,
+                            &actual_identity_thread, holdgit,
+                            proxy_thread.as_ref(), &agents, terminal)
+                    } else {
+                        netfile::cache_source(&netfile_source, &integs,
+                            &actual_identity_thread, skipint,
+                            proxy_thread.as_ref(), &agents, &mirrors_thread,
+                            terminal)
+                    }
                 });
                 netfile_threads.push(netfile_thread);
             }
@@ -104,8 +160,9 @@ pub(crate) fn cache_sources_mt(
         {
             let git_threads = 
                 get_domain_threads_from_map(domain, &mut git_threads_map)?;
+            let max_threads = limits.threads(*domain, MAX_THREADS);
             while git_repos.len() > 0 &&
-                git_threads.len() < MAX_THREADS
+                git_threads.len() < max_threads
             {
                 let git_repo = git_repos
                     .pop()
@@ -120,6 +177,32 @@ pub(crate) fn cache_sources_mt(
                 git_threads.push(git_thread);
             }
         }
+        for (domain, vcs_sources) in
+            vcs_sources_map.iter_mut()
+        {
+            let vcs_threads =
+                get_domain_threads_from_map(domain, &mut vcs_threads_map)?;
+            let max_threads = limits.threads(*domain, MAX_THREADS);
+            while vcs_sources.len() > 0 &&
+                vcs_threads.len() < max_threads
+            {
+                let vcs_source = vcs_sources
+                    .pop()
+                    .expect("Failed to get source from sources vec");
+                if holdgit && vcs::healthy(&vcs_source) {
+                    continue
+                }
+                let proxy_thread = proxy
+                    .map(|proxy|proxy.to_owned());
+                let actual_identity_thread = actual_identity.clone();
+                let limits_thread = limits.clone();
+                let vcs_thread = thread::spawn(
+                move || vcs::cache_source(&vcs_source,
+                    &actual_identity_thread, proxy_thread.as_ref(),
+                    &limits_thread));
+                vcs_threads.push(vcs_thread);
+            }
+        }
         if let Err(_) = threading::wait_thread_map(
             &mut netfile_threads_map, "caching netfile sources") {
                 bad = true
@@ -128,10 +211,16 @@ pub(crate) fn cache_sources_mt(
             &mut git_threads_map, "caching git sources") {
                 bad = true
             }
+        if let Err(_) = threading::wait_thread_map(
+            &mut vcs_threads_map, "caching VCS sources") {
+                bad = true
+            }
         netfile_sources_map.retain(
             |_, sources| sources.len() > 0);
         git_repos_map.retain(
             |_, repos| repos.len() > 0);
+        vcs_sources_map.retain(
+            |_, sources| sources.len() > 0);
     }
     let mut remaining_threads = vec![];
     for mut threads in
@@ -144,6 +233,11 @@ pub(crate) fn cache_sources_mt(
     {
         remaining_threads.append(&mut threads);
     }
+    for mut threads in
+        vcs_threads_map.into_values()
+    {
+        remaining_threads.append(&mut threads);
+    }
     match threading::wait_remaining(remaining_threads, "caching sources") {
         Ok(_) => (),
         Err(_) => bad = true,
@@ -15,7 +15,10 @@ pub(super) use crypto::{
     Sha512sum,
 };
 pub(super) use md5::Md5sum;
-pub(super) use integ::IntegFile;
+pub(super) use integ::{
+    Integ,
+    sha256_string,
+};
 
 
 pub(super) trait Sum {
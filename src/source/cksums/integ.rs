@@ -1,9 +1,6 @@
 use std::{
-        fs::{File, remove_file, rename},
-        path::{
-            PathBuf,
-            Path
-        },
+        fs::File,
+        path::Path,
     };
 
 use super::ck::Cksum;
@@ -23,7 +20,7 @@ use crate::error::{
         Result
     };
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub(crate) enum Integ {
     CK (Cksum),
     MD5 (Md5sum),
@@ -35,18 +32,23 @@ pub(crate) enum Integ {
     B2 (B2sum),
 }
 
-pub(crate) struct IntegFile {
-    pub(crate) path: PathBuf,
-    pub(crate) integ: Integ
-}
-
-impl IntegFile {
-    pub(crate) fn get_path(&self) -> &Path {
-        self.path.as_path()
+impl Integ {
+    /// Name of the algorithm as in makepkg's [algo]sums arrays
+    pub(crate) fn algo(&self) -> &'static str {
+        match self {
+            Integ::CK ( _ ) => "ck",
+            Integ::MD5 ( _ ) => "md5",
+            Integ::SHA1 ( _ ) => "sha1",
+            Integ::SHA224 ( _ ) => "sha224",
+            Integ::SHA256 ( _ ) => "sha256",
+            Integ::SHA384 ( _ ) => "sha384",
+            Integ::SHA512 ( _ ) => "sha512",
+            Integ::B2 ( _ ) => "b2",
+        }
     }
 
-    pub(crate) fn from_integ(parent: &str, integ: Integ) -> Self {
-        let name = match &integ {
+    pub(crate) fn sum_string(&self) -> String {
+        match self {
             Integ::CK ( sum ) => sum.to_string(),
             Integ::MD5 ( sum ) => sum.to_string(),
             Integ::SHA1 ( sum ) => sum.to_string(),
@@ -55,155 +57,83 @@ impl IntegFile {
             Integ::SHA384 ( sum ) => sum.to_string(),
             Integ::SHA512 ( sum ) => sum.to_string(),
             Integ::B2 ( sum ) => sum.to_string(),
-        };
-        Self { path: PathBuf::from(format!("{}/{}", parent, name)), integ}
+        }
     }
 
-    pub(crate) fn valid(&self, skipint: bool) -> bool {
-        if ! self.path.exists() {
-            log::error!("Integ file '{}' does not exist", self.path.display());
-            return false
-        }
-        if skipint {
-            log::warn!("Integrity check skipped for existing '{}'",
-                        self.path.display());
-            return true
-        }
-        let valid = match File::open(&self.path) {
-            Ok(mut file) => {
-                let file = &mut file;
-                match &self.integ {
-                    Integ::CK ( sum ) =>
-                        Cksum::sum(file).as_ref() == Some(sum),
-                    Integ::MD5 ( sum ) =>
-                        Md5sum::sum(file).as_ref() == Some(sum),
-                    Integ::SHA1 ( sum ) =>
-                        Sha1sum::sum(file).as_ref() == Some(sum),
-                    Integ::SHA224 ( sum ) =>
-                        Sha224sum::sum(file).as_ref() == Some(sum),
-                    Integ::SHA256 ( sum ) =>
-                        Sha256sum::sum(file).as_ref() == Some(sum),
-                    Integ::SHA384 ( sum ) =>
-                        Sha384sum::sum(file).as_ref() == Some(sum),
-                    Integ::SHA512 ( sum ) =>
-                        Sha512sum::sum(file).as_ref() == Some(sum),
-                    Integ::B2 ( sum ) =>
-                        B2sum::sum(file).as_ref() == Some(sum),
-                }
-            },
+    /// The checksum of the file in the same algorithm
+    pub(crate) fn sum_file(&self, path: &Path) -> Option<Self> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
             Err(e) => {
                 log::error!("Failed to open file '{}': {}",
-                            self.path.display(), e);
-                false
+                            path.display(), e);
+                return None
             },
         };
-        if ! valid {
-            match std::fs::remove_file(&self.path) {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!(
-                        "Failed to remove bad file '{}': {}",
-                              self.path.display(), e);
-                },
-            }
-        }
-        return valid;
-    }
-
-    pub(crate) fn clone_file_from(&self, source: &Self) -> Result<()> {
-        if let Err(e) = super::super::download::clone_file(
-            &source.path, &self.path)
-        {
-            log::error!("Failed to clone '{}' from '{}': {}",
-                        self.path.display(),
-                        source.path.display(),
-                        e);
-            return Err(e)
-        }
-        if self.valid(false) {
-            Ok(())
-        } else {
-            log::error!("Cloned integ file not healthy");
-            Err(Error::IntegrityError)
-        }
+        let file = &mut file;
+        Some(match self {
+            Integ::CK ( _ ) => Integ::CK ( Cksum::sum(file)? ),
+            Integ::MD5 ( _ ) => Integ::MD5 ( Md5sum::sum(file)? ),
+            Integ::SHA1 ( _ ) => Integ::SHA1 ( Sha1sum::sum(file)? ),
+            Integ::SHA224 ( _ ) => Integ::SHA224 ( Sha224sum::sum(file)? ),
+            Integ::SHA256 ( _ ) => Integ::SHA256 ( Sha256sum::sum(file)? ),
+            Integ::SHA384 ( _ ) => Integ::SHA384 ( Sha384sum::sum(file)? ),
+            Integ::SHA512 ( _ ) => Integ::SHA512 ( Sha512sum::sum(file)? ),
+            Integ::B2 ( _ ) => Integ::B2 ( B2sum::sum(file)? ),
+        })
     }
 
-    pub(crate) fn absorb(&self, source: Self) -> Result<()> {
-        if self.path.exists() {
-            if let Err(e) = remove_file(&self.path) {
-                log::error!("Failed to remove existing '{}': {}",
-                    self.path.display(), e);
-                return Err(Error::IoError(e))
-            }
-        }
-        match rename(&source.path, &self.path) {
-            Ok(()) => return Ok(()),
-            Err(e) => {
-                log::error!("Failed to move '{}' to '{}': {}",
-                    source.path.display(), self.path.display(), e);
-            },
-        }
-        // Failed to move, then do light copy (hard link) or read+write copy
-        if let Err(e) = self.clone_file_from(&source) {
-            log::error!("Failed to clone '{}' from '{}'",
-                self.path.display(), source.path.display(),);
-            return Err(e)
-        }
-        if let Err(e) = remove_file(&source.path) {
-            log::error!("Failed to remove source file '{}': {}",
-                source.path.display(), e);
-            return Err(Error::IoError(e))
+    pub(crate) fn verify(&self, path: &Path) -> bool {
+        let valid = self.sum_file(path).as_ref() == Some(self);
+        if ! valid {
+            log::error!("File '{}' does not match {}sum '{}'",
+                path.display(), self.algo(), self.sum_string());
         }
-        Ok(())
+        valid
     }
 
     pub(crate) fn vec_from_source(source: &super::super::Source) -> Vec<Self> {
-        let mut integ_files = vec![];
+        let mut integs = vec![];
         if let Some(sum) = &source.ck {
-            integ_files.push(Self::from_integ(
-                "sources/file-ck", Integ::CK ( sum.clone() )))
+            integs.push(Integ::CK ( sum.clone() ))
         }
         if let Some(sum) = &source.md5 {
-            integ_files.push(Self::from_integ(
-                "sources/file-md5", Integ::MD5 ( sum.clone() )))
+            integs.push(Integ::MD5 ( sum.clone() ))
         }
         if let Some(sum) = &source.sha1 {
-            integ_files.push(Self::from_integ
-                ("sources/file-sha1", Integ::SHA1 ( sum.clone() )))
+            integs.push(Integ::SHA1 ( sum.clone() ))
         }
         if let Some(sum) = &source.sha224 {
-            integ_files.push(Self::from_integ(
-                "sources/file-sha224", Integ::SHA224 ( sum.clone() )))
+            integs.push(Integ::SHA224 ( sum.clone() ))
         }
         if let Some(sum) = &source.sha256 {
-            integ_files.push(Self::from_integ(
-                "sources/file-sha256", Integ::SHA256 ( sum.clone() )))
+            integs.push(Integ::SHA256 ( sum.clone() ))
         }
         if let Some(sum) = &source.sha384 {
-            integ_files.push(Self::from_integ(
-                "sources/file-sha384", Integ::SHA384 ( sum.clone() )))
+            integs.push(Integ::SHA384 ( sum.clone() ))
         }
         if let Some(sum) = &source.sha512 {
-            integ_files.push(Self::from_integ(
-                "sources/file-sha512", Integ::SHA512 ( sum.clone() )))
+            integs.push(Integ::SHA512 ( sum.clone() ))
         }
         if let Some(sum) = &source.b2 {
-            integ_files.push(Self::from_integ(
-                "sources/file-b2", Integ::B2 ( sum.clone() )))
+            integs.push(Integ::B2 ( sum.clone() ))
         }
-        integ_files
-
+        integs
     }
+}
 
-    pub(crate) fn temp(&self) -> Result<Self> {
-        let mut name = self.path.file_name().ok_or_else(||{
-            log::error!("Path has no ending name");
-            Error::ImpossibleLogic
-        })?.to_owned();
-        name.push(".temp");
-        Ok(Self {
-            path: self.path.with_file_name(name),
-            integ: self.integ.clone(),
-        })
+/// The canonical name of a file in the blob store
+pub(crate) fn sha256_string(path: &Path) -> Result<String> {
+    let mut file = File::open(path).map_err(|e|{
+        log::error!("Failed to open file '{}': {}", path.display(), e);
+        Error::IoError(e)
+    })?;
+    match Sha256sum::sum(&mut file) {
+        Some(sum) => Ok(sum.to_string()),
+        None => {
+            log::error!("Failed to calculate sha256sum of '{}'",
+                path.display());
+            Err(Error::IntegrityError)
+        },
     }
-}
\ No newline at end of file
+}
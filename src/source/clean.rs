@@ -11,7 +11,15 @@ use std::{
         }
     };
 use xxhash_rust::xxh3::xxh3_64;
-use crate::source::Source;
+use crate::source::{
+        blob,
+        Integ,
+        netfile,
+        pgp,
+        Protocol,
+        Source,
+        VcsProtocol,
+    };
 
 // Used must be already sorted
 pub(crate) fn remove_unused<P: AsRef<Path>>(dir: P, used: &Vec<String>) {
@@ -48,67 +56,70 @@ pub(crate) fn remove_unused<P: AsRef<Path>>(dir: P, used: &Vec<String>) {
     }
 }
 
-fn clean_netfile_sources(sources: &Vec<Source>) -> Vec<JoinHandle<()>>{
-    let mut ck_used = vec![];
-    let mut md5_used = vec![];
-    let mut sha1_used = vec![];
-    let mut sha224_used = vec![];
-    let mut sha256_used = vec![];
-    let mut sha384_used = vec![];
-    let mut sha512_used = vec![];
-    let mut b2_used = vec![];
-    let mut cleaners = vec![];
+/// Mark the blobs still reachable from the sources, then sweep all other
+/// blobs, and the index entries and signature verifications of them
+fn clean_blobs(sources: &Vec<Source>) {
+    let mut marked = vec![];
     for source in sources.iter() {
-        if let Some(ck) = &source.ck {
-            ck_used.push(ck.to_string());
-        }
-        if let Some(md5) = &source.md5 {
-            md5_used.push(md5.to_string());
-        }
-        if let Some(sha1) = &source.sha1 {
-            sha1_used.push(sha1.to_string());
-        }
-        if let Some(sha224) = &source.sha224 {
-            sha224_used.push(sha224.to_string());
+        let integs = Integ::vec_from_source(source);
+        if let Some(sha256) = blob::lookup(&integs) {
+            marked.push(sha256)
         }
-        if let Some(sha256) = &source.sha256 {
-            sha256_used.push(sha256.to_string());
+        // Partial downloads are kept to be resumed
+        if let Some(name) = blob::temp_path(&integs).ok().as_ref()
+            .and_then(|temp| temp.file_name())
+        {
+            marked.push(name.to_string_lossy().into_owned())
         }
-        if let Some(sha384) = &source.sha384 {
-            sha384_used.push(sha384.to_string());
+    }
+    marked.sort_unstable();
+    marked.dedup();
+    remove_unused(blob::BLOBS, &marked);
+    if let Ok(readdir) = read_dir(pgp::VERIFIED) {
+        for entry in readdir.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.split_once('-').is_some_and(
+                |(sha256, _)| marked.binary_search(&sha256.to_string()).is_ok())
+            {
+                continue
+            }
+            log::info!("Removing '{}' not used any more",
+                entry.path().display());
+            let _ = remove_file(entry.path());
         }
-        if let Some(sha512) = &source.sha512 {
-            sha512_used.push(sha512.to_string());
+    }
+    for algo in blob::INDEXED {
+        let readdir = match read_dir(blob::index_dir(algo)) {
+            Ok(readdir) => readdir,
+            Err(_) => continue,
+        };
+        for entry in readdir.flatten() {
+            if blob::read_index(&entry.path()).is_some_and(
+                |sha256| marked.binary_search(&sha256).is_ok())
+            {
+                continue
+            }
+            log::info!("Removing '{}' not used any more",
+                entry.path().display());
+            let _ = remove_file(entry.path());
         }
-        if let Some(b2) = &source.b2 {
-            b2_used.push(b2.to_string());
+    }
+}
+
+fn clean_netfile_sources(sources: Vec<Source>) -> Vec<JoinHandle<()>> {
+    let mut url_used = vec![];
+    for source in sources.iter() {
+        if ! netfile::has_integ(source) {
+            let name = format!("{:016x}", source.hash_url);
+            url_used.push(format!("{}.validators", name));
+            url_used.push(name);
         }
     }
-    ck_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-ck", &ck_used)));
-    md5_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-md5", &md5_used)));
-    sha1_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-sha1", &sha1_used)));
-    sha224_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-sha224", &sha224_used)));
-    sha256_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-sha256", &sha256_used)));
-    sha384_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-sha384", &sha384_used)));
-    sha512_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-sha512", &sha512_used)));
-    b2_used.sort_unstable();
-    cleaners.push(thread::spawn(move ||
-        remove_unused("sources/file-b2", &b2_used)));
-    cleaners
+    url_used.sort_unstable();
+    vec![
+        thread::spawn(move || remove_unused("sources/file-url", &url_used)),
+        thread::spawn(move || clean_blobs(&sources)),
+    ]
 }
 
 fn clean_git_sources(sources: &Vec<Source>) {
@@ -120,11 +131,30 @@ fn clean_git_sources(sources: &Vec<Source>) {
     remove_unused("sources/git", &used);
 }
 
-pub(crate) fn cleanup(netfile_sources: Vec<Source>, git_sources: Vec<Source>)
-    -> Vec<JoinHandle<()>>
+fn clean_vcs_sources(sources: &Vec<Source>) {
+    for vcs in [VcsProtocol::Bzr, VcsProtocol::Fossil, VcsProtocol::Hg,
+        VcsProtocol::Svn]
+    {
+        let mut used: Vec<String> = sources.iter().filter_map(
+            |source| match &source.protocol {
+                Protocol::Vcs { protocol } if protocol == &vcs =>
+                    Some(format!("{:016x}", source.hash_url)),
+                _ => None,
+            }).collect();
+        used.sort_unstable();
+        remove_unused(vcs.cache_dir(), &used);
+    }
+}
+
+pub(crate) fn cleanup(
+    netfile_sources: Vec<Source>,
+    git_sources: Vec<Source>,
+    vcs_sources: Vec<Source>
+) -> Vec<JoinHandle<()>>
 {
     let mut cleaners =
-        clean_netfile_sources(&netfile_sources);
+        clean_netfile_sources(netfile_sources);
     cleaners.push(thread::spawn(move||clean_git_sources(&git_sources)));
+    cleaners.push(thread::spawn(move||clean_vcs_sources(&vcs_sources)));
     cleaners
 }
\ No newline at end of file
@@ -1,16 +1,29 @@
+mod auth;
 mod file;
 mod ftp;
 mod http;
+mod progress;
 mod rsync;
 mod scp;
 
 const BUFFER_SIZE: usize = 0x400000; // 4M
 
+use progress::Progress;
+
+pub(crate) use auth::{
+    get_following,
+    HttpAuth,
+};
 pub(crate) use file::{
     clone_file,
     file
 };
 pub(crate) use ftp::ftp;
-pub(crate) use http::http;
+pub(crate) use http::{
+    http,
+    http_conditional,
+    HttpAgents,
+    Validators,
+};
 pub(crate) use rsync::rsync;
 pub(crate) use scp::scp;
\ No newline at end of file
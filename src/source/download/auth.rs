@@ -0,0 +1,188 @@
+// Credentials for HTTP(S) downloads and the AUR RPC, added per host by an
+// agent middleware so they never end up in URLs, which are logged everywhere.
+// Header values and netrc passwords are read once and never logged. Agents
+// with them never redirect by themselves, as ureq would resend all headers but
+// Authorization to the new host, redirects are followed by get_following()
+// instead, so every hop only gets the headers of its own host, and never
+// through a downgrade from HTTPS
+
+use std::{
+        collections::HashMap,
+        fs::read_to_string,
+        path::Path,
+        sync::Arc,
+    };
+
+use base64::Engine;
+use url::Url;
+
+use crate::{
+        config::HttpHeader,
+        error::{
+            Error,
+            Result,
+        },
+    };
+
+type Headers = HashMap<String, Vec<(String, String)>>; // host: [(name, value)]
+
+const MAX_REDIRECTS: usize = 5;
+
+/// Per host headers to add to requests, cheap to clone into agents
+#[derive(Clone, Default)]
+pub(crate) struct HttpAuth {
+    headers: Arc<Headers>,
+}
+
+fn header_value(host: &str, header: &HttpHeader) -> Result<String> {
+    let mut value = header.value.clone();
+    if ! header.file.is_empty() {
+        match read_to_string(&header.file) {
+            Ok(secret) => value.push_str(secret.trim()),
+            Err(e) => {
+                log::error!("Failed to read value of header '{}' for '{}' from \
+                    '{}': {}", header.name, host, header.file, e);
+                return Err(Error::IoError(e))
+            },
+        }
+    } else if ! header.env.is_empty() {
+        match std::env::var(&header.env) {
+            Ok(secret) => value.push_str(secret.trim()),
+            Err(_) => {
+                log::error!("Env '{}' for value of header '{}' for '{}' is not \
+                    set", header.env, header.name, host);
+                return Err(Error::InvalidConfig)
+            },
+        }
+    }
+    Ok(value)
+}
+
+/// Logins of named machines in a netrc file, the default entry is ignored so
+/// a password is never sent to a host not explicitly listed
+fn parse_netrc(content: &str) -> HashMap<String, (String, String)> {
+    let mut logins = HashMap::new();
+    let mut machine: Option<String> = None;
+    let mut login = String::new();
+    let mut password = None;
+    let mut tokens = content.split_whitespace();
+    loop {
+        let token = tokens.next();
+        if matches!(token, None | Some("machine") | Some("default") |
+            Some("macdef"))
+        {
+            if let (Some(machine), Some(password)) =
+                (machine.take(), password.take())
+            {
+                logins.insert(machine, (login.clone(), password));
+            }
+            login.clear();
+            password = None;
+        }
+        match token {
+            None | Some("macdef") => break, // Macros are only at the end
+            Some("machine") => machine = tokens.next().map(String::from),
+            Some("login") => login = tokens.next().unwrap_or_default().into(),
+            Some("password") => password = tokens.next().map(String::from),
+            _ => (),
+        }
+    }
+    logins
+}
+
+impl HttpAuth {
+    pub(crate) fn new(
+        rules: &HashMap<String, Vec<HttpHeader>>, netrc: &Path
+    ) -> Result<Self>
+    {
+        let mut headers = Headers::new();
+        for (host, rule) in rules.iter() {
+            let mut host_headers = vec![];
+            for header in rule.iter() {
+                host_headers.push(
+                    (header.name.clone(), header_value(host, header)?));
+            }
+            headers.insert(host.clone(), host_headers);
+        }
+        let logins = match read_to_string(netrc) {
+            Ok(content) => parse_netrc(&content),
+            Err(_) => HashMap::new(),
+        };
+        for (host, (login, password)) in logins {
+            let host_headers = headers.entry(host.clone()).or_default();
+            if host_headers.iter().any(
+                |(name, _)| name.eq_ignore_ascii_case("authorization"))
+            {
+                log::info!("Ignoring netrc login of '{}' as it has an \
+                    Authorization header rule", host);
+                continue
+            }
+            log::info!("Using netrc login of '{}' from '{}'",
+                host, netrc.display());
+            host_headers.push((String::from("Authorization"), format!(
+                "Basic {}", base64::engine::general_purpose::STANDARD.encode(
+                    format!("{}:{}", login, password)))));
+        }
+        headers.retain(|_, host_headers| ! host_headers.is_empty());
+        Ok(Self { headers: Arc::new(headers) })
+    }
+
+    /// An agent adding our headers, optionally through the proxy
+    pub(crate) fn agent_builder(&self) -> ureq::AgentBuilder {
+        let builder = ureq::AgentBuilder::new();
+        if self.headers.is_empty() {
+            builder
+        } else {
+            builder.middleware(self.clone()).redirects(0)
+        }
+    }
+}
+
+/// GET the URL, following redirects that the agent left to us, the request of
+/// every hop set up by the closure, e.g. with a range
+pub(crate) fn get_following<F>(agent: &ureq::Agent, url: &str, setup: F)
+    -> std::result::Result<ureq::Response, ureq::Error>
+where
+    F: Fn(ureq::Request) -> ureq::Request
+{
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let response = setup(agent.get(&url)).call()?;
+        if ! matches!(response.status(), 301 | 302 | 303 | 307 | 308) {
+            return Ok(response)
+        }
+        let next = match response.header("location").and_then(|location|
+            Url::parse(&url).and_then(|base| base.join(location)).ok())
+        {
+            Some(next) => next,
+            None => return Ok(response),
+        };
+        // Headers of the host would be sent in cleartext to the same host
+        if url.starts_with("https:") && next.scheme() != "https" {
+            log::error!("Refused to follow redirect from '{}' to '{}', which \
+                downgrades from HTTPS", url, next);
+            return Err(std::io::Error::other(format!(
+                "HTTPS downgraded by redirect to '{}'", next)).into())
+        }
+        log::info!("Redirected from '{}' to '{}'", url, next);
+        url = next.to_string()
+    }
+    Err(std::io::Error::other(format!(
+        "Too many redirects, last to '{}'", url)).into())
+}
+
+impl ureq::Middleware for HttpAuth {
+    fn handle(&self, request: ureq::Request, next: ureq::MiddlewareNext)
+        -> std::result::Result<ureq::Response, ureq::Error>
+    {
+        let host = request.request_url().ok()
+            .map(|url| url.host().to_string());
+        let mut request = request;
+        if let Some(headers) = host.and_then(|host| self.headers.get(&host)) {
+            for (name, value) in headers.iter() {
+                request = request.set(name, value)
+            }
+        }
+        next.handle(request)
+    }
+}
@@ -1,60 +1,285 @@
 use std::{
-        fs::File,
-        io::Read,
+        collections::HashMap,
+        fs::{
+            metadata,
+            read_to_string,
+            remove_file,
+            write,
+            File,
+            OpenOptions,
+        },
+        io::{
+            Read,
+            Write,
+        },
         path::Path,
     };
 
-use crate::error::{
-        Error,
-        Result,
+use crate::{
+        error::{
+            Error,
+            Result,
+        },
+        source::{
+            limit::{
+                DomainLimits,
+                Throttle,
+            },
+            Proxy,
+        },
     };
 
-pub(crate) fn http(url: &str, path: &Path, proxy: Option<&str>)
-    -> Result<()>
-{
-    let mut target = match File::create(path) {
-        Ok(target) => target,
-        Err(e) => {
-            log::error!("Failed to open {} as write-only: {}",
-                        path.display(), e);
-            return Err(Error::IoError(e))
-        },
+use super::{
+        BUFFER_SIZE,
+        get_following,
+        HttpAuth,
+        Progress,
     };
-    let response = match proxy {
-        Some(proxy) => {
+
+/// Validators of a cached download, to revalidate it with conditional
+/// requests, stored as `etag:...` and `last-modified:...` lines
+#[derive(Default)]
+pub(crate) struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn from_file(path: &Path) -> Self {
+        let mut validators = Self::default();
+        let content = match read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return validators,
+        };
+        for line in content.lines() {
+            if let Some(etag) = line.strip_prefix("etag:") {
+                validators.etag = Some(etag.to_string())
+            } else if let Some(last_modified) =
+                line.strip_prefix("last-modified:")
+            {
+                validators.last_modified = Some(last_modified.to_string())
+            }
+        }
+        validators
+    }
+
+    pub(crate) fn to_file(&self, path: &Path) -> Result<()> {
+        let mut content = String::new();
+        if let Some(etag) = &self.etag {
+            content.push_str(&format!("etag:{}\n", etag))
+        }
+        if let Some(last_modified) = &self.last_modified {
+            content.push_str(&format!("last-modified:{}\n", last_modified))
+        }
+        if let Err(e) = write(path, content) {
+            log::error!("Failed to write validators to '{}': {}",
+                path.display(), e);
+            return Err(Error::IoError(e))
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    fn from_response(response: &ureq::Response) -> Self {
+        Self {
+            etag: response.header("etag").map(|etag| etag.to_string()),
+            last_modified: response.header("last-modified").map(
+                |last_modified| last_modified.to_string()),
+        }
+    }
+}
+
+/// Agents shared by all downloads from the same domain, so their connection
+/// pools are reused, one direct and one through each proxy if set
+#[derive(Clone)]
+pub(crate) struct HttpAgents {
+    direct: ureq::Agent,
+    proxied: HashMap<String, ureq::Agent>,
+    limits: DomainLimits,
+}
+
+impl HttpAgents {
+    pub(crate) fn new(
+        proxy: Option<&Proxy>, auth: &HttpAuth, limits: &DomainLimits
+    ) -> Result<Self>
+    {
+        let mut proxied = HashMap::new();
+        for proxy in proxy.iter().flat_map(|proxy| proxy.urls()) {
+            if proxied.contains_key(proxy) {
+                continue
+            }
             let proxy_opt = ureq::Proxy::new(proxy).map_err(|e|
             {
-                log::error!("Failed to create proxy from '{}': {}", proxy, e);
+                log::error!("Failed to create proxy from '{}': {}",
+                    proxy, e);
                 Error::UreqError(e)
             })?;
-            ureq::AgentBuilder::new().proxy(proxy_opt).build().get(url)
-        },
-        None => ureq::get(url),
-    }.call().map_err(
+            proxied.insert(proxy.to_string(),
+                auth.agent_builder().proxy(proxy_opt).build());
+        }
+        Ok(Self {
+            direct: auth.agent_builder().build(),
+            proxied,
+            limits: limits.clone(),
+        })
+    }
+
+    /// Also applied to FTP downloads, which go without agents
+    pub(crate) fn limits(&self) -> &DomainLimits {
+        &self.limits
+    }
+
+    pub(crate) fn get(&self, proxy: Option<&str>) -> &ureq::Agent {
+        match proxy.and_then(|proxy| self.proxied.get(proxy)) {
+            Some(proxied) => proxied,
+            None => &self.direct,
+        }
+    }
+}
+
+fn call<F>(agent: &ureq::Agent, url: &str, setup: F) -> Result<ureq::Response>
+where
+    F: Fn(ureq::Request) -> ureq::Request
+{
+    get_following(agent, url, setup).map_err(
         |e|{
             log::error!("Failed to GET url '{}': {}", url, e);
             Error::UreqError(e)
-        })?;
+        })
+}
+
+fn save(
+    response: ureq::Response, url: &str, path: &Path, mut target: File,
+    offset: u64, throttle: &Throttle, terminal: bool
+) -> Result<()>
+{
     let len = match response.header("content-length") {
-        Some(len) => len.parse().unwrap(),
+        Some(len) => len.parse().ok(),
         None => {
             log::info!("Warning: response does not have 'content-length', limit \
                 max download size to 4GiB");
-            0x100000000
+            None
         }
     };
-    match std::io::copy(
-        &mut response.into_reader().take(len), &mut target)
+    let mut progress = Progress::new(
+        url, terminal, offset, len.map(|len| len + offset));
+    let mut reader = response.into_reader().take(len.unwrap_or(0x100000000));
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let size = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(size) => size,
+            Err(e) => {
+                log::error!("Failed to read download '{}' after {} bytes: {}",
+                    url, offset + progress.done, e);
+                return Err(Error::IoError(e))
+            },
+        };
+        if let Err(e) = target.write_all(&buffer[0..size]) {
+            log::error!("Failed to write download '{}' into '{}': {}",
+                        url, path.display(), e);
+            return Err(Error::IoError(e))
+        }
+        progress.update(size);
+        throttle.consume(size as u64);
+    }
+    progress.finish();
+    if let Some(len) = len {
+        if progress.done < len {
+            log::error!("Download '{}' ended early, {} of {} bytes",
+                url, progress.done, len);
+            return Err(Error::IntegrityError)
+        }
+    }
+    log::info!("Downloaded {} bytes from '{}' into '{}', {:.2} MiB/s",
+        progress.done, url, path.display(), progress.speed());
+    Ok(())
+}
+
+fn open(path: &Path, append: bool) -> Result<File> {
+    let mut options = OpenOptions::new();
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).create(true).truncate(true);
+    }
+    options.open(path).map_err(|e|{
+        log::error!("Failed to open {} as write-only: {}", path.display(), e);
+        Error::IoError(e)
+    })
+}
+
+/// Download into the file, resuming from where a previous attempt stopped if
+/// the file exists and the server supports range requests
+pub(crate) fn http(
+    url: &str, path: &Path, agents: &HttpAgents, proxy: Option<&str>,
+    terminal: bool
+) -> Result<()>
+{
+    let offset = match metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let throttle = agents.limits.request(url);
+    if offset > 0 {
+        log::info!("Resuming download of '{}' from byte {}", url, offset);
+    }
+    let range = format!("bytes={}-", offset);
+    let response = match get_following(agents.get(proxy), url, |request|
+        if offset > 0 {
+            request.set("Range", &range)
+        } else {
+            request
+        })
     {
-        Ok(size) => {
-            log::info!("Downloaded {} bytes from '{}' into '{}'",
-                size, url, path.display());
-            Ok(())
+        Ok(response) => response,
+        Err(ureq::Error::Status(416, _)) => {
+            log::error!("Server refused to resume '{}' from byte {}, \
+                discarding the partial download", url, offset);
+            let _ = remove_file(path);
+            return Err(Error::IntegrityError)
         },
         Err(e) => {
-            log::error!("Failed to copy download '{}' into '{}': {}",
-                        url, path.display(), e);
-            Err(Error::IoError(e))
+            log::error!("Failed to GET url '{}': {}", url, e);
+            return Err(Error::UreqError(e))
         },
+    };
+    let resumed = offset > 0 && response.status() == 206 &&
+        response.header("content-range").is_some_and(
+            |range| range.starts_with(&format!("bytes {}-", offset)));
+    if offset > 0 && ! resumed {
+        log::info!("Server does not support resuming '{}', restarting", url)
+    }
+    let target = open(path, resumed)?;
+    save(response, url, path, target, if resumed { offset } else { 0 },
+        &throttle, terminal)
+}
+
+/// Download only if the remote file changed since the cached one described by
+/// the validators, returning the new validators if downloaded
+pub(crate) fn http_conditional(
+    url: &str, path: &Path, agents: &HttpAgents, proxy: Option<&str>,
+    validators: &Validators, terminal: bool
+) -> Result<Option<Validators>>
+{
+    let throttle = agents.limits.request(url);
+    let response = call(agents.get(proxy), url, |mut request| {
+        if let Some(etag) = &validators.etag {
+            request = request.set("If-None-Match", etag)
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.set("If-Modified-Since", last_modified)
+        }
+        request
+    })?;
+    if response.status() == 304 {
+        log::info!("Cached download of '{}' is still fresh", url);
+        return Ok(None)
     }
-}
\ No newline at end of file
+    let validators = Validators::from_response(&response);
+    save(response, url, path, open(path, false)?, 0, &throttle, terminal)?;
+    Ok(Some(validators))
+}
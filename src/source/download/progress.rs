@@ -0,0 +1,95 @@
+use std::{
+        io::Write,
+        sync::Mutex,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+const MIB: f64 = 1048576.0;
+
+/// The terminal line shared by all concurrent downloads, true if a progress
+/// line without its newline is on it
+static LINE: Mutex<bool> = Mutex::new(false);
+
+pub(super) struct Progress<'a> {
+    url: &'a str,
+    terminal: bool,
+    start: Instant,
+    last: Instant,
+    offset: u64,
+    pub(super) done: u64,
+    total: Option<u64>,
+    printed: bool,
+}
+
+impl<'a> Progress<'a> {
+    pub(super) fn new(
+        url: &'a str, terminal: bool, offset: u64, total: Option<u64>
+    ) -> Self
+    {
+        let now = Instant::now();
+        Self { url, terminal, start: now, last: now, offset, done: 0, total,
+            printed: false }
+    }
+
+    pub(super) fn speed(&self) -> f64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.done as f64 / MIB / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn report(&self) -> String {
+        let current = (self.offset + self.done) as f64 / MIB;
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{:.1}/{:.1} MiB ({:3}%), {:.2} MiB/s", current,
+                total as f64 / MIB, (self.offset + self.done) * 100 / total,
+                self.speed()),
+            _ => format!("{:.1} MiB, {:.2} MiB/s", current, self.speed()),
+        }
+    }
+
+    pub(super) fn update(&mut self, size: usize) {
+        self.done += size as u64;
+        let interval = if self.terminal { 1 } else { 10 };
+        if self.last.elapsed() < Duration::from_secs(interval) {
+            return
+        }
+        self.last = Instant::now();
+        if self.terminal {
+            let mut line = LINE.lock().unwrap_or_else(|e| e.into_inner());
+            let mut stdout = std::io::stdout().lock();
+            let _ = write!(stdout, "\r\x1b[KDownloading '{}': {}",
+                self.url, self.report());
+            let _ = stdout.flush();
+            *line = true;
+            self.printed = true
+        } else {
+            log::info!("Downloading '{}': {}", self.url, self.report())
+        }
+    }
+
+    /// End the progress line, if any, so later logs start on their own line
+    pub(super) fn finish(&mut self) {
+        if ! self.printed {
+            return
+        }
+        self.printed = false;
+        let mut line = LINE.lock().unwrap_or_else(|e| e.into_inner());
+        if *line {
+            println!();
+            *line = false
+        }
+    }
+}
+
+impl Drop for Progress<'_> {
+    fn drop(&mut self) {
+        self.finish()
+    }
+}
@@ -6,12 +6,16 @@ use std::path::{
 use xxhash_rust::xxh3::xxh3_64;
 
 use crate::{
-        error::Result,
+        error::{
+            Error,
+            Result
+        },
         source::{
-            VcsProtocol,
+            blob,
+            netfile,
             Protocol,
             Source,
-            IntegFile,
+            Integ,
         }
     };
 
@@ -21,17 +25,25 @@ pub(crate) fn extract<P: AsRef<Path>>(dir: P, sources: &Vec<Source>) -> Result<(
         let mut original = None;
         match &source.protocol {
             Protocol::Netfile { protocol: _ } => {
-                let integ_files = IntegFile::vec_from_source(source);
-                if let Some(integ_file) = integ_files.last() {
-                    original = Some(rel.join(integ_file.get_path()));
+                let integs = Integ::vec_from_source(source);
+                if integs.is_empty() {
+                    original = Some(rel.join(netfile::url_cache_path(source)))
+                } else {
+                    match blob::lookup(&integs) {
+                        Some(sha256) =>
+                            original = Some(rel.join(blob::blob_path(&sha256))),
+                        None => {
+                            log::error!("Source '{}' not in blob store",
+                                source.url);
+                            return Err(Error::IntegrityError)
+                        },
+                    }
                 }
             },
             Protocol::Vcs { protocol } =>
-                if let VcsProtocol::Git = protocol {
-                    original = Some(rel
-                        .join(format!("sources/git/{:016x}",
-                                xxh3_64(source.url.as_bytes()))));
-                },
+                original = Some(rel
+                    .join(format!("{}/{:016x}", protocol.cache_dir(),
+                            xxh3_64(source.url.as_bytes())))),
             Protocol::Local => (),
         }
         if let Some(original) = original {
@@ -5,6 +5,8 @@ use git2::{
         Branch,
         build::CheckoutBuilder,
         Commit,
+        Cred,
+        CredentialType,
         FetchOptions,
         Oid,
         Remote,
@@ -24,22 +26,48 @@ use std::{
             PathBuf
         },
         os::unix::fs::MetadataExt,
-        str::FromStr,
         thread,
     };
 
 use crate::{
+        config::GitCredential,
         error::{
             Error,
             Result
         },
         source::{
             aur::AurResult,
+            DomainLimits,
+            HttpAuth,
+            limit::Throttle,
+            proxy::{
+                attempts,
+                Client,
+                for_git,
+            },
             Proxy
         },
         threading
     };
 
+mod fragment;
+mod gmr;
+mod maintenance;
+mod rules;
+mod submodule;
+
+pub(crate) use fragment::{
+        Fragment,
+        resolve_fragments,
+    };
+pub(crate) use gmr::Gmr;
+pub(crate) use maintenance::maintain;
+pub(crate) use rules::GitRules;
+pub(crate) use submodule::{
+        cache_submodules,
+        set_command_config,
+    };
+
 const REFSPECS_HEADS_TAGS: &[&str] = &[
     "+refs/heads/*:refs/heads/*",
     "+refs/tags/*:refs/tags/*"
@@ -48,40 +76,15 @@ const REFSPECS_HEADS_TAGS: &[&str] = &[
 const _REFSPECS_MASTER_ONLY: &[&str] =
     &["+refs/heads/master:refs/heads/master"];
 
-pub(crate) struct Gmr {
-    prefix: String,
-}
-
-impl Gmr {
-    pub(crate) fn init(gmr: &str) -> Self {
-        Self {
-            prefix: gmr.to_owned()
-        }
-    }
-
-    fn get_mirror_url(&self, orig: &str) -> Option<String> {
-        let orig_url = Url::from_str(orig).ok()?;
-        let mut mirror_url = self.prefix.clone();
-        mirror_url.push('/');
-        mirror_url.push_str(orig_url.host_str()?);
-        mirror_url.push_str(orig_url.path());
-        Some(mirror_url)
-    }
-}
-
-fn optional_gmr(gmr: Option<&Gmr>, orig: &str) -> Option<String> {
-    match gmr {
-        Some(gmr) => gmr.get_mirror_url(orig),
-        None => None,
-    }
-}
-
 pub(crate) struct Repo {
     path: PathBuf,
     url: String,
-    mirror: Option<String>,
+    gmr: Option<Gmr>,
     repo: Repository,
     branches: Vec<String>,
+    depth: i32, // 0 for full history
+    credential: Option<GitCredential>,
+    limits: DomainLimits,
 }
 
 pub(crate) trait ToReposMap {
@@ -215,14 +218,91 @@ fn gcb_transfer_progress(progress: Progress<'_>) -> bool {
     true
 }
 
-fn fetch_opts_init<'a>(terminal: bool) -> FetchOptions<'a> {
+fn read_token(credential: &GitCredential) -> Option<String> {
+    if ! credential.token_file.is_empty() {
+        match std::fs::read_to_string(&credential.token_file) {
+            Ok(token) => return Some(token.trim().to_string()),
+            Err(e) => log::error!("Failed to read git token file '{}': {}",
+                credential.token_file, e),
+        }
+    }
+    if ! credential.token_env.is_empty() {
+        match std::env::var(&credential.token_env) {
+            Ok(token) => return Some(token.trim().to_string()),
+            Err(_) => log::error!("Git token env '{}' is not set",
+                credential.token_env),
+        }
+    }
+    None
+}
+
+fn credential_for(
+    credential: &GitCredential, username_from_url: Option<&str>,
+    allowed: CredentialType
+) -> std::result::Result<Cred, git2::Error>
+{
+    let username = if ! credential.username.is_empty() {
+        credential.username.as_str()
+    } else {
+        username_from_url.unwrap_or("git")
+    };
+    if allowed.contains(CredentialType::SSH_KEY) {
+        if ! credential.ssh_key.is_empty() {
+            return Cred::ssh_key(
+                username, None, Path::new(&credential.ssh_key), None)
+        }
+        if credential.ssh_agent {
+            return Cred::ssh_key_from_agent(username)
+        }
+    }
+    if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        if let Some(token) = read_token(credential) {
+            return Cred::userpass_plaintext(username, &token)
+        }
+    }
+    if allowed.contains(CredentialType::USERNAME) {
+        return Cred::username(username)
+    }
+    Err(git2::Error::from_str("no configured credential fits the remote"))
+}
+
+fn fetch_opts_init<'a>(
+    terminal: bool, credential: Option<&GitCredential>, throttle: Throttle
+) -> FetchOptions<'a>
+{
     let mut cbs = RemoteCallbacks::new();
+    if let Some(credential) = credential {
+        let credential = credential.clone();
+        let mut tries = 0;
+        // libgit2 keeps asking on rejection, give up instead of looping
+        cbs.credentials(move |_, username_from_url, allowed| {
+            tries += 1;
+            if tries > 3 {
+                return Err(git2::Error::from_str(
+                    "credential rejected by the remote"))
+            }
+            credential_for(&credential, username_from_url, allowed)
+        });
+    }
     if terminal {
         cbs.sideband_progress(|log| {
                 print!("Remote: {}", String::from_utf8_lossy(log));
                 true
             });
-        cbs.transfer_progress(gcb_transfer_progress);
+    }
+    if terminal || throttle.is_limited() {
+        let mut last = 0;
+        cbs.transfer_progress(move |progress| {
+            // Received bytes start over when a fetch is retried
+            let received = progress.received_bytes() as u64;
+            throttle.consume(received.saturating_sub(last));
+            last = received;
+            if terminal {
+                gcb_transfer_progress(progress)
+            } else {
+                true
+            }
+        });
     }
     let mut fetch_opts =
         FetchOptions::new();
@@ -248,12 +328,22 @@ fn fetch_remote(
     tries: usize
 ) -> Result<()>
 {
-    let (tries_without_proxy, tries_with_proxy) = match proxy {
-        Some(proxy) => (proxy.after, tries),
-        None => (tries, 0),
-    };
+    let attempts = attempts(proxy,
+        remote.url().unwrap_or_default(), Client::Git, tries);
     let mut last_error = Error::ImpossibleLogic;
-    for _ in 0..tries_without_proxy {
+    for (i, attempt) in attempts.iter().enumerate() {
+        if i == 0 || attempts[i - 1] != *attempt {
+            let mut proxy_opts = ProxyOptions::new();
+            if let Some(proxy) = attempt {
+                if i > 0 {
+                    log::error!("Failed to fetch from remote '{}' after {} \
+                        tries, will use proxy '{}' to retry",
+                        remote_safe_url(&remote), i, proxy);
+                }
+                proxy_opts.url(&for_git(proxy)?);
+            }
+            fetch_opts.proxy_options(proxy_opts);
+        }
         match remote.fetch(
             refspecs, Some(fetch_opts), None
         ) {
@@ -265,35 +355,8 @@ fn fetch_remote(
             },
         }
     }
-    let proxy = match proxy {
-        Some(proxy) => proxy,
-        None => {
-            log::error!("Failed to fetch from remote '{}' after {} tries and \
-                there's no proxy to retry, giving up", remote_safe_url(&remote),
-                tries_without_proxy);
-            return Err(last_error)
-        },
-    };
-    if tries_without_proxy > 0 {
-        log::error!("Failed to fetch from remote '{}' after {} tries, will use \
-            proxy to retry", remote_safe_url(&remote), tries_without_proxy);
-    }
-    let mut proxy_opts = ProxyOptions::new();
-    proxy_opts.url(&proxy.url);
-    fetch_opts.proxy_options(proxy_opts);
-    for _ in 0..tries_with_proxy {
-        match remote.fetch(
-            refspecs, Some(fetch_opts), None) {
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                log::error!("Failed to fetch from remote '{}': {}",
-                    remote_safe_url(&remote), e);
-                last_error = e.into()
-            },
-        }
-    };
-    log::error!("Failed to fetch from remote '{}' even with proxy",
-        remote_safe_url(&remote));
+    log::error!("Failed to fetch from remote '{}' after {} tries, giving up",
+        remote_safe_url(&remote), attempts.len());
     Err(last_error)
 }
 
@@ -313,18 +376,28 @@ impl Repo {
         }
     }
 
+    fn with_repository<P: AsRef<Path>>(
+        path: P, url: &str, gmr: Option<&Gmr>, repo: Repository
+    ) -> Self
+    {
+        Self {
+            path: path.as_ref().to_owned(),
+            url: url.to_owned(),
+            gmr: gmr.cloned(),
+            repo,
+            branches: vec![],
+            depth: 0,
+            credential: None,
+            limits: DomainLimits::default(),
+        }
+    }
+
     fn init_bare<P: AsRef<Path>>(path: P, url: &str, gmr: Option<&Gmr>)
         -> Result<Self>
     {
         match Repository::init_bare(&path) {
             Ok(repo) => {
-                let repo = Self {
-                    path: path.as_ref().to_owned(),
-                    url: url.to_owned(),
-                    mirror: optional_gmr(gmr, url),
-                    repo,
-                    branches: vec![],
-                };
+                let repo = Self::with_repository(path, url, gmr, repo);
                 repo.add_remote().and(Ok(repo))
             },
             Err(e) => {
@@ -340,28 +413,43 @@ impl Repo {
     ) -> Result<Self>
     {
         match Repository::open_bare(&path) {
-            Ok(repo) => Ok(Self {
-                path: path.as_ref().to_owned(),
-                url: url.to_owned(),
-                mirror: optional_gmr(gmr, url),
-                repo,
-                branches: vec![],
-            }),
-            Err(e) => {
-                if e.class() == ErrorClass::Os &&
-                e.code() == ErrorCode::NotFound {
+            Ok(repo) => Ok(Self::with_repository(path, url, gmr, repo)),
+            Err(e) => match (e.class(), e.code()) {
+                (ErrorClass::Os, ErrorCode::NotFound) =>
+                    Self::init_bare(path, url, gmr),
+                // Not a repo anymore, e.g. HEAD or objects gone, or an
+                // unparsable config: start over and let the sync clone it
+                (ErrorClass::Repository, ErrorCode::NotFound) |
+                (ErrorClass::Config, ErrorCode::GenericError) => {
+                    log::error!("Failed to open {}: {}, re-creating it",
+                            path.as_ref().display(), e);
+                    if let Err(e) = std::fs::remove_dir_all(&path) {
+                        log::error!("Failed to remove corrupt repo {}: {}",
+                            path.as_ref().display(), e);
+                        return Err(e.into())
+                    }
                     Self::init_bare(path, url, gmr)
-                } else {
+                },
+                // Permissions, ownership, etc, which re-creating won't fix
+                _ => {
                     log::error!("Failed to open {}: {}",
                             path.as_ref().display(), e);
                     Err(e.into())
-                }
+                },
             },
         }
     }
 
+    /// Open only an existing repo, never creating or re-creating it
+    pub(super) fn open_existing<P: AsRef<Path>>(path: P, url: &str)
+        -> Option<Self>
+    {
+        Repository::open_bare(&path).ok().map(
+            |repo| Self::with_repository(path, url, None, repo))
+    }
+
     fn _with_gmr(&mut self, gmr: &Gmr) {
-        self.mirror = gmr.get_mirror_url(&self.url)
+        self.gmr = Some(gmr.clone())
     }
 
     fn update_head_raw(repo: &Repository, remote: &mut Remote)
@@ -393,19 +481,34 @@ impl Repo {
         Ok(())
     }
 
+    /// Fetch from the URL, the actual remote or a mirror of it, credentials
+    /// are only sent to the actual remote
     fn sync_raw(
-        repo: &Repository, url: &str, proxy: Option<&Proxy>, refspecs: &[&str],
+        &self, url: &str, proxy: Option<&Proxy>, refspecs: &[&str],
         tries: usize, terminal: bool
     ) -> Result<()>
     {
         let mut remote =
-            repo.remote_anonymous(url).map_err(Error::from)?;
-        let mut fetch_opts = fetch_opts_init(terminal);
+            self.repo.remote_anonymous(url).map_err(Error::from)?;
+        let credential = if url == self.url {
+            self.credential.as_ref()
+        } else {
+            None
+        };
+        let mut fetch_opts = fetch_opts_init(
+            terminal, credential, self.limits.request(url));
+        if self.depth > 0 {
+            fetch_opts.depth(self.depth);
+        }
         fetch_remote(&mut remote, &mut fetch_opts, proxy, refspecs, tries)?;
-        Self::update_head_raw(repo, &mut remote)?;
+        Self::update_head_raw(&self.repo, &mut remote)?;
         Ok(())
     }
 
+    pub(crate) fn set_limits(&mut self, limits: &DomainLimits) {
+        self.limits = limits.clone()
+    }
+
     pub(crate) fn sync(&self, proxy: Option<&Proxy>, terminal: bool)
         -> Result<()>
     {
@@ -422,18 +525,15 @@ impl Repo {
             }
             refspecs = refspecs_ref.as_slice()
         }
-        if let Some(mirror) = &self.mirror {
-            log::info!("Syncing repo '{}' with gmr '{}' before actual remote",
-                        &self.path.display(), &mirror);
-            if let Ok(_) = Self::sync_raw(
-                &self.repo, &mirror, None, refspecs, 1, terminal
-            ) {
-                return Ok(())
-            }
+        if self.sync_mirrors(refspecs, terminal) {
+            return Ok(())
         }
         log::info!("Syncing repo '{}' with '{}' ",
             &self.path.display(), &self.url);
-        Self::sync_raw(&self.repo, &self.url, proxy, refspecs, 3, terminal)
+        self.sync_raw(&self.url, proxy, refspecs, 3, terminal)?;
+        log::info!("Repo '{}' served by actual remote '{}'",
+            &self.path.display(), &self.url);
+        Ok(())
     }
 
     fn get_branch<'a>(&'a self, branch: &str) -> Result<Branch<'a>> {
@@ -597,8 +697,8 @@ impl Repo {
 
     fn get_domain(&self) -> String {
         if let Ok(url) = Url::parse(&self.url) {
-            if let Some(domain) = url.domain() {
-                return domain.to_string()
+            if let Some(host) = url.host_str() {
+                return host.to_string()
             }
         }
         format!("of url {}", &self.url)
@@ -698,18 +798,20 @@ impl Repo {
 
     fn sync_for_aur(
         mut repos: Vec<Self>,
+        max_threads: usize,
         hold: bool,
         proxy: Option<&Proxy>,
+        auth: &HttpAuth,
         terminal: bool
     ) -> Result<()>
     {
-        if Self::filter_aur(&mut repos).is_err() {
+        if Self::filter_aur(&mut repos, auth).is_err() {
             log::error!("Warning: failed to filter AUR repos")
         }
         if repos.is_empty() {
             return Ok(())
         }
-        Self::sync_for_domain(repos, 1, hold, proxy, terminal)
+        Self::sync_for_domain(repos, max_threads, hold, proxy, terminal)
     }
 
     fn last_fetch(&self) -> i64 {
@@ -725,7 +827,7 @@ impl Repo {
         metadata.mtime()
     }
 
-    fn filter_aur(repos: &mut Vec<Self>) -> Result<()> {
+    fn filter_aur(repos: &mut Vec<Self>, auth: &HttpAuth) -> Result<()> {
         let mut pkgs: Vec<String> = Vec::new();
         for repo in repos.iter() {
             let url = match Url::parse(&repo.url) {
@@ -743,90 +845,60 @@ impl Repo {
             log::error!("Pkgs and repos len mismatch");
             return Err(Error::ImpossibleLogic)
         }
-        let mut aur_result = match AurResult::from_pkgs(&pkgs) {
+        let mut aur_result = match AurResult::from_pkgs(&pkgs, auth) {
             Ok(aur_result) => aur_result,
             Err(e) => {
                 log::error!("Failed to get result from AUR RPC");
                 return Err(e)
             },
         };
-        if aur_result.results.len() == repos.len() {
-            let mut i = 0;
-            while i < repos.len() {
-                let repo = match repos.get(i) {
-                    Some(repo) => repo,
-                    None => {
-                        log::error!("Failed to get repo");
-                        return Err(Error::ImpossibleLogic)
-                    },
-                };
-                let pkg = match aur_result.results.get(i) {
-                    Some(pkg) => pkg,
+        // Batches and cached results are not in the order of pkgs
+        aur_result.results.sort_unstable_by(
+            |result_a, result_b|
+                result_a.name.cmp(&result_b.name));
+        let mut i = 0;
+        while i < repos.len() {
+            let repo = match repos.get(i) {
+                Some(repo) => repo,
+                None => {
+                    log::error!("Failed to get repo");
+                    return Err(Error::ImpossibleLogic)
+                },
+            };
+            let pkg = match pkgs.get(i) {
+                Some(pkg) => pkg,
+                None => {
+                    log::error!("Failed to get pkg");
+                    return Err(Error::ImpossibleLogic)
+                },
+            };
+            if let Ok(j) = aur_result.results.binary_search_by(
+                |result|result.name.cmp(pkg))
+            {
+                let result = match
+                    aur_result.results.get(j)
+                {
+                    Some(result) => result,
                     None => {
-                        log::error!("Failed to get pkg");
+                        log::error!("Failed to get result");
                         return Err(Error::ImpossibleLogic)
                     },
                 };
                 // leave a 1-min window
-                if repo.last_fetch() > pkg.last_modified + 60 {
-                    log::info!(
-                        "Repo '{}' last fetch later than AUR last modified, \
-                        skippping it", &repo.url);
+                if repo.last_fetch() > result.last_modified + 60 {
+                    log::info!("Repo '{}' last fetch later than AUR last \
+                        modified, skippping it", &repo.url);
                     repos.swap_remove(i);
-                    aur_result.results.swap_remove(i);
+                    pkgs.swap_remove(i);
                 } else {
                     log::info!("Repo '{}' needs update from AUR",
                         &repo.url);
                     i += 1
                 }
-            }
-        } else {
-            aur_result.results.sort_unstable_by(
-                |result_a, result_b|
-                    result_a.name.cmp(&result_b.name));
-            let mut i = 0;
-            while i < repos.len() {
-                let repo = match repos.get(i) {
-                    Some(repo) => repo,
-                    None => {
-                        log::error!("Failed to get repo");
-                        return Err(Error::ImpossibleLogic)
-                    },
-                };
-                let pkg = match pkgs.get(i) {
-                    Some(pkg) => pkg,
-                    None => {
-                        log::error!("Failed to get pkg");
-                        return Err(Error::ImpossibleLogic)
-                    },
-                };
-                if let Ok(j) = aur_result.results.binary_search_by(
-                    |result|result.name.cmp(pkg))
-                {
-                    let result = match
-                        aur_result.results.get(j)
-                    {
-                        Some(result) => result,
-                        None => {
-                            log::error!("Failed to get result");
-                            return Err(Error::ImpossibleLogic)
-                        },
-                    };
-                    if repo.last_fetch() > result.last_modified + 60 {
-                        log::info!("Repo '{}' last fetch later than AUR last \
-                            modified, skippping it", &repo.url);
-                        repos.swap_remove(i);
-                        pkgs.swap_remove(i);
-                    } else {
-                        log::info!("Repo '{}' needs update from AUR",
-                            &repo.url);
-                        i += 1
-                    }
-                } else { // Can not find
-                    log::info!("Repo '{}' not found, needs update from AUR",
-                        &repo.url);
-                    i += 1
-                }
+            } else { // Can not find
+                log::info!("Repo '{}' not found, needs update from AUR",
+                    &repo.url);
+                i += 1
             }
         }
         log::info!("Filtered AUR repos");
@@ -837,23 +909,31 @@ impl Repo {
         repos_map: HashMap<u64, Vec<Self>>,
         hold: bool,
         proxy: Option<&Proxy>,
+        auth: &HttpAuth,
+        limits: &DomainLimits,
         terminal: bool
     ) -> Result<()>
     {
         log::info!("Syncing repos with {} groups", repos_map.len());
         let mut threads = vec![];
-        for (domain, repos) in repos_map {
+        for (domain, mut repos) in repos_map {
+            for repo in repos.iter_mut() {
+                repo.set_limits(limits)
+            }
             let proxy_thread = proxy.and_then(
                 |proxy_actual|Some(proxy_actual.to_owned()));
             if domain == 0xb463cbdec08d6265 {
+                let auth_thread = auth.clone();
+                let max_threads = limits.threads(domain, 1);
                 threads.push(thread::spawn(move || {
-                    Self::sync_for_aur(
-                        repos, hold, proxy_thread.as_ref(), terminal)}))
+                    Self::sync_for_aur(repos, max_threads, hold,
+                        proxy_thread.as_ref(), &auth_thread, terminal)}))
 
             } else {
+                let max_threads = limits.threads(domain, 10);
                 threads.push(thread::spawn(move || {
                     Self::sync_for_domain(
-                        repos, 10, hold,
+                        repos, max_threads, hold,
                         proxy_thread.as_ref(), terminal)}))
             }
         }
@@ -0,0 +1,149 @@
+// Fragments of git sources, like #tag=v1.2, are resolved against the cached
+// bare repos before any chroot work, so a missing ref fails early
+
+use git2::Oid;
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::{
+            CacheSettings,
+            Protocol,
+            Proxy,
+            Source,
+            VcsProtocol,
+        },
+    };
+
+use super::{
+        Repo,
+        ToReposMap,
+    };
+
+#[derive(Clone)]
+pub(crate) enum Fragment {
+    Branch (String),
+    Commit (String),
+    Tag (String),
+}
+
+impl Fragment {
+    pub(crate) fn from_raw_string(raw: &[u8]) -> Option<Self> {
+        let raw = String::from_utf8_lossy(raw);
+        let (kind, value) = raw.split_once('=')?;
+        let value = value.to_string();
+        match kind {
+            "branch" => Some(Self::Branch(value)),
+            "commit" => Some(Self::Commit(value)),
+            "tag" => Some(Self::Tag(value)),
+            _ => {
+                log::warn!("Unknown git source fragment '{}'", raw);
+                None
+            },
+        }
+    }
+
+    fn refspec(&self) -> String {
+        match self {
+            Self::Branch(branch) =>
+                format!("+refs/heads/{}:refs/heads/{}", branch, branch),
+            // Keep fetched commits referenced so they survive gc
+            Self::Commit(commit) =>
+                format!("+{}:refs/fragments/{}", commit, commit),
+            Self::Tag(tag) => format!("+refs/tags/{}:refs/tags/{}", tag, tag),
+        }
+    }
+}
+
+impl std::fmt::Display for Fragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Branch(branch) => write!(f, "branch={}", branch),
+            Self::Commit(commit) => write!(f, "commit={}", commit),
+            Self::Tag(tag) => write!(f, "tag={}", tag),
+        }
+    }
+}
+
+impl Repo {
+    fn resolve_fragment(&self, fragment: &Fragment) -> Option<Oid> {
+        let spec = match fragment {
+            Fragment::Branch(branch) => format!("refs/heads/{}", branch),
+            Fragment::Commit(commit) => commit.clone(),
+            Fragment::Tag(tag) => format!("refs/tags/{}", tag),
+        };
+        self.repo.revparse_single(&spec).ok()?.peel_to_commit().ok()
+            .map(|commit| commit.id())
+    }
+
+    fn fetch_fragment(
+        &self, fragment: &Fragment, proxy: Option<&Proxy>, terminal: bool
+    ) -> Result<()>
+    {
+        let refspec = fragment.refspec();
+        log::info!("Fetching '{}' of '{}'", fragment, self.url);
+        if self.sync_mirrors(&[&refspec], terminal) {
+            return Ok(())
+        }
+        self.sync_raw(&self.url, proxy, &[&refspec], 3, terminal)
+    }
+}
+
+/// Resolve fragments of all git sources of a PKGBUILD to commits, fetching
+/// missing refs unless holding git, and return the URLs and commits of the
+/// resolved ones
+pub(crate) fn resolve_fragments(
+    pkgbase: &str, sources: &[Source], settings: &CacheSettings
+) -> Result<Vec<(String, Oid)>>
+{
+    let &CacheSettings { holdgit, proxy, gmr, limits, rules, terminal, .. }
+        = settings;
+    let mut resolved = vec![];
+    let mut bad = false;
+    for source in sources.iter() {
+        let fragment = match (&source.protocol, &source.fragment) {
+            (Protocol::Vcs { protocol: VcsProtocol::Git }, Some(fragment)) =>
+                fragment,
+            _ => continue,
+        };
+        let mut repo = source.to_repo("sources/git", gmr, None)?;
+        rules.apply_one(&mut repo);
+        repo.set_limits(limits);
+        let commit = match repo.resolve_fragment(fragment) {
+            Some(commit) => Some(commit),
+            None if holdgit => {
+                log::error!("PKGBUILD '{}': '{}' not found in cache of '{}', \
+                    not fetching it as git is held", pkgbase, fragment,
+                    source.url);
+                None
+            },
+            None => {
+                log::warn!("PKGBUILD '{}': '{}' not found in cache of '{}', \
+                    fetching it", pkgbase, fragment, source.url);
+                match repo.fetch_fragment(fragment, proxy, terminal) {
+                    Ok(()) => repo.resolve_fragment(fragment),
+                    Err(_) => None,
+                }
+            },
+        };
+        match commit {
+            Some(commit) => {
+                log::info!("PKGBUILD '{}': '{}' of '{}' resolved to {}",
+                    pkgbase, fragment, source.url, commit);
+                resolved.push((source.url.clone(), commit))
+            },
+            None => {
+                log::error!("PKGBUILD '{}': could not resolve '{}' of git \
+                    source '{}'", pkgbase, fragment, source.url);
+                bad = true
+            },
+        }
+    }
+    if bad {
+        Err(Error::BrokenPKGBUILDs(vec![pkgbase.to_string()]))
+    } else {
+        Ok(resolved)
+    }
+}
@@ -0,0 +1,101 @@
+// Mirrors laid out like 7Ji/git-mirrorer, e.g. git://gmr.lan/github.com/foo
+// for https://github.com/foo, tried in order before the actual remote. A
+// mirror failing several fetches in a row is skipped for the rest of the run,
+// so a dead one does not add a timeout to every fetch
+
+use std::{
+        str::FromStr,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+        },
+    };
+
+use url::Url;
+
+use super::Repo;
+
+const MAX_FAILURES: usize = 3;
+
+struct Mirror {
+    prefix: String,
+    failures: AtomicUsize, // in a row, reset by any success
+}
+
+/// All mirrors and their health in this run, cheap to clone into repos
+#[derive(Clone)]
+pub(crate) struct Gmr {
+    mirrors: Arc<Vec<Mirror>>,
+}
+
+impl Gmr {
+    /// None if there's no prefix at all
+    pub(crate) fn init(prefixes: &[String]) -> Option<Self> {
+        let mirrors: Vec<Mirror> = prefixes.iter()
+            .filter(|prefix| ! prefix.is_empty())
+            .map(|prefix| Mirror {
+                prefix: prefix.trim_end_matches('/').to_owned(),
+                failures: AtomicUsize::new(0),
+            }).collect();
+        if mirrors.is_empty() {
+            None
+        } else {
+            Some(Self { mirrors: Arc::new(mirrors) })
+        }
+    }
+
+    fn mirror_url(prefix: &str, orig: &str) -> Option<String> {
+        let orig_url = Url::from_str(orig).ok()?;
+        let mut mirror_url = prefix.to_owned();
+        mirror_url.push('/');
+        mirror_url.push_str(orig_url.host_str()?);
+        mirror_url.push_str(orig_url.path());
+        Some(mirror_url)
+    }
+
+    fn report(&self, mirror: &Mirror, ok: bool) {
+        if ok {
+            mirror.failures.store(0, Ordering::Relaxed);
+            return
+        }
+        if mirror.failures.fetch_add(1, Ordering::Relaxed) + 1 == MAX_FAILURES
+        {
+            log::warn!("Gmr '{}' failed {} times in a row, skipping it for \
+                the rest of this run", mirror.prefix, MAX_FAILURES)
+        }
+    }
+}
+
+impl Repo {
+    /// Fetch from the healthy mirrors in order, true if one served the repo
+    pub(super) fn sync_mirrors(&self, refspecs: &[&str], terminal: bool)
+        -> bool
+    {
+        let gmr = match &self.gmr {
+            Some(gmr) => gmr,
+            None => return false,
+        };
+        for mirror in gmr.mirrors.iter() {
+            if mirror.failures.load(Ordering::Relaxed) >= MAX_FAILURES {
+                continue
+            }
+            let url = match Gmr::mirror_url(&mirror.prefix, &self.url) {
+                Some(url) => url,
+                None => continue,
+            };
+            log::info!("Syncing repo '{}' with gmr '{}' before actual remote",
+                self.path.display(), url);
+            let ok = self.sync_raw(&url, None, refspecs, 1, terminal).is_ok();
+            gmr.report(mirror, ok);
+            if ok {
+                log::info!("Repo '{}' served by gmr '{}'",
+                    self.path.display(), mirror.prefix);
+                return true
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,196 @@
+// Bare repos under sources/git and sources/PKGBUILD are periodically checked
+// for connectivity and repacked with git's CLI, as libgit2 has neither fsck
+// nor gc. A repo found corrupt is removed and cloned again, gmr first
+
+use std::{
+        fs::{
+            File,
+            read_dir,
+            remove_dir_all,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::{
+            Command,
+            Stdio,
+        },
+        time::{
+            Duration,
+            SystemTime,
+        },
+    };
+
+use crate::{
+        identity::IdentityActual,
+        source::{
+            DomainLimits,
+            Proxy,
+        },
+    };
+
+use super::{
+        GitRules,
+        Gmr,
+        Repo,
+    };
+
+const PARENTS: [&str; 2] = ["sources/git", "sources/PKGBUILD"];
+const STAMP: &str = "arb-maintained";
+
+fn due(repo: &Path, interval: Duration) -> bool {
+    match repo.join(STAMP).metadata().and_then(|metadata| metadata.modified())
+    {
+        Ok(modified) => SystemTime::now().duration_since(modified)
+            .map_or(true, |elapsed| elapsed >= interval),
+        Err(_) => true,
+    }
+}
+
+fn origin_url(repo: &Path) -> Option<String> {
+    git2::Config::open(&repo.join("config")).ok()?
+        .get_string("remote.origin.url").ok()
+}
+
+// What fsck prints for broken objects or links, as opposed to e.g. a refusal
+// for dubious ownership or a permission error, which recloning won't fix
+const CORRUPTIONS: [&str; 9] = ["missing blob", "missing tree",
+    "missing commit", "missing tag", "broken link", "bad object",
+    "is corrupt", "invalid sha1 pointer", "bad sha1 file"];
+const REFUSALS: [&str; 2] = ["dubious ownership", "Permission denied"];
+
+enum Outcome {
+    Ok,
+    Corrupt,
+    Failed,
+}
+
+/// Run git on the repo, only Corrupt if git ran and failed complaining about
+/// broken objects, so a missing git or other failures never lose the repo
+fn git(repo: &Path, args: &[&str], actual_identity: &IdentityActual, job: &str)
+    -> Outcome
+{
+    let output = match actual_identity.set_root_drop_command(
+        Command::new("/usr/bin/git")
+            .arg("-C")
+            .arg(repo)
+            .args(args))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            log::error!("Failed to spawn child {}: {}", job, e);
+            return Outcome::Failed
+        },
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match output.status.code() {
+        Some(0) => Outcome::Ok,
+        Some(code) => {
+            log::error!("Child {} bad return {}: {}", job, code, stderr.trim());
+            if CORRUPTIONS.iter().any(|line| stderr.contains(line)) &&
+                ! REFUSALS.iter().any(|line| stderr.contains(line))
+            {
+                Outcome::Corrupt
+            } else {
+                Outcome::Failed
+            }
+        },
+        None => {
+            log::error!("Failed to get return code of child {}", job);
+            Outcome::Failed
+        },
+    }
+}
+
+fn reclone(
+    path: &Path, url: &str, gmr: Option<&Gmr>, rules: &GitRules,
+    limits: &DomainLimits, proxy: Option<&Proxy>, terminal: bool
+) {
+    log::warn!("Repo '{}' is corrupt, cloning '{}' again", path.display(), url);
+    if let Err(e) = remove_dir_all(path) {
+        log::error!("Failed to remove corrupt repo '{}': {}", path.display(), e);
+        return
+    }
+    let mut repo = match Repo::open_bare(path, url, gmr) {
+        Ok(repo) => repo,
+        Err(_) => return,
+    };
+    rules.apply_one(&mut repo);
+    repo.set_limits(limits);
+    if repo.sync(proxy, terminal).is_err() {
+        log::warn!("Failed to clone '{}' again, would retry on next sync", url)
+    }
+}
+
+fn maintain_one(
+    path: &Path, actual_identity: &IdentityActual, gmr: Option<&Gmr>,
+    rules: &GitRules, limits: &DomainLimits, proxy: Option<&Proxy>,
+    terminal: bool
+) {
+    let url = match origin_url(path) {
+        Some(url) => url,
+        None => {
+            log::warn!("Repo '{}' has no origin, leaving it alone",
+                path.display());
+            return
+        },
+    };
+    log::info!("Maintaining repo '{}' of '{}'", path.display(), url);
+    match git(path, &["fsck", "--connectivity-only", "--no-progress"],
+        actual_identity, "to check connectivity of git repo")
+    {
+        Outcome::Ok => (),
+        Outcome::Corrupt => {
+            reclone(path, &url, gmr, rules, limits, proxy, terminal);
+            return
+        },
+        Outcome::Failed => {
+            log::warn!("Failed to check repo '{}', leaving it alone",
+                path.display());
+            return
+        },
+    }
+    if ! matches!(git(path, &["gc", "--quiet", "--prune=now"], actual_identity,
+        "to gc git repo"), Outcome::Ok)
+    {
+        log::warn!("Failed to gc repo '{}'", path.display());
+        return
+    }
+    if let Err(e) = File::create(path.join(STAMP)) {
+        log::warn!("Failed to stamp repo '{}' as maintained: {}",
+            path.display(), e)
+    }
+}
+
+/// Check and repack all cached repos not maintained in the last given days,
+/// 0 to never maintain them
+pub(crate) fn maintain(
+    days: u64, actual_identity: &IdentityActual, gmr: Option<&Gmr>,
+    rules: &GitRules, limits: &DomainLimits, proxy: Option<&Proxy>,
+    terminal: bool
+) {
+    if days == 0 {
+        return
+    }
+    let interval = Duration::from_secs(days * 86400);
+    for parent in PARENTS {
+        let readdir = match read_dir(parent) {
+            Ok(readdir) => readdir,
+            Err(_) => continue,
+        };
+        let mut repos: Vec<PathBuf> = readdir.flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && due(path, interval))
+            .collect();
+        repos.sort_unstable();
+        for repo in repos.iter() {
+            maintain_one(repo, actual_identity, gmr, rules, limits, proxy,
+                terminal)
+        }
+    }
+}
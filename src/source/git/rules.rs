@@ -0,0 +1,83 @@
+// Per URL prefix settings of git remotes, the longest matching prefix wins.
+// Huge git sources could be mirrored shallowly with depth limits. libgit2 has
+// no partial clone support, and a blobless mirror would need network during
+// build anyway, so only depth limits are offered. Private remotes could be
+// fetched with credentials, which only refer to secrets, never contain them
+
+use std::collections::HashMap;
+
+use crate::config::GitCredential;
+
+use super::Repo;
+
+fn longest_first<T: Clone>(rules: &HashMap<String, T>) -> Vec<(String, T)> {
+    let mut rules: Vec<(String, T)> = rules.iter().map(
+        |(prefix, value)| (prefix.clone(), value.clone())).collect();
+    rules.sort_unstable_by_key(
+        |(prefix, _)| std::cmp::Reverse(prefix.len()));
+    rules
+}
+
+/// Whether the prefix covers the URL, only at a path segment boundary, so
+/// `https://github.com/org` does not cover `https://github.com/org-evil/repo`
+fn covers(prefix: &str, url: &str) -> bool {
+    match url.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') ||
+                        prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+fn find<'a, T>(rules: &'a [(String, T)], url: &str) -> Option<&'a (String, T)>
+{
+    rules.iter().find(|(prefix, _)| covers(prefix, url))
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct GitRules {
+    depths: Vec<(String, i32)>, // Longest prefix first
+    credentials: Vec<(String, GitCredential)>, // Longest prefix first
+}
+
+impl GitRules {
+    pub(crate) fn new(
+        depths: &HashMap<String, i32>,
+        credentials: &HashMap<String, GitCredential>
+    ) -> Self
+    {
+        Self {
+            depths: longest_first(depths),
+            credentials: longest_first(credentials),
+        }
+    }
+
+    /// The depth of the longest matching prefix, 0 for full history
+    fn depth(&self, url: &str) -> i32 {
+        match find(&self.depths, url) {
+            Some((_, depth)) if *depth > 0 => *depth,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn apply(&self, repos_map: &mut HashMap<u64, Vec<Repo>>) {
+        for repos in repos_map.values_mut() {
+            for repo in repos.iter_mut() {
+                self.apply_one(repo)
+            }
+        }
+    }
+
+    pub(super) fn apply_one(&self, repo: &mut Repo) {
+        repo.depth = self.depth(&repo.url);
+        if repo.depth > 0 {
+            log::info!("Repo '{}' would be fetched with depth {}",
+                repo.path.display(), repo.depth)
+        }
+        repo.credential = find(&self.credentials, &repo.url).map(
+            |(prefix, credential)| {
+                log::info!("Repo '{}' would be fetched with credential of \
+                    prefix '{}'", repo.path.display(), prefix);
+                credential.clone()
+            });
+    }
+}
@@ -0,0 +1,313 @@
+// Submodules of git sources are mirrored into sources/git like git sources,
+// so `git submodule update` in prepare() or build() needs no network. The
+// mirrors are resolved through url.<mirror>.insteadOf rewrites written to
+// sources/gitconfig, which extractors and builders include via GIT_CONFIG_*.
+// Relative submodule URLs are resolved by git against the local clone's origin
+// there, which no rewrite matches, so they get submodule.<name>.url instead
+
+use std::{
+        collections::{
+            BTreeMap,
+            BTreeSet,
+            HashSet,
+        },
+        fs::{
+            remove_file,
+            write,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+        str::FromStr,
+    };
+
+use git2::{
+        ObjectType,
+        Oid,
+    };
+use url::Url;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::{
+            DomainLimits,
+            HttpAuth,
+            MapByDomain,
+            Protocol,
+            Proxy,
+            Source,
+            VcsProtocol,
+        },
+    };
+
+use super::{
+        GitRules,
+        Gmr,
+        Repo,
+        ToReposMap,
+    };
+
+const CONFIG: &str = "sources/gitconfig";
+
+struct Submodule {
+    name: String,
+    url: String,
+    relative: bool,
+    commit: Oid,
+}
+
+/// Resolve a relative submodule URL against the URL of its superproject
+fn resolve_url(base: &str, url: &str) -> String {
+    if ! url.starts_with("./") && ! url.starts_with("../") {
+        return url.to_string()
+    }
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            if let Some(slash) = base.rfind('/') {
+                base.truncate(slash)
+            }
+            rest = stripped
+        } else {
+            break
+        }
+    }
+    format!("{}/{}", base, rest)
+}
+
+/// Names, paths and URLs of all submodules declared in .gitmodules
+fn parse_gitmodules(content: &str) -> Vec<(String, String, String)> {
+    let mut submodules = vec![];
+    let mut name = None;
+    let mut path = None;
+    let mut url = None;
+    for line in content.lines().chain(std::iter::once("[end]")) {
+        let line = line.trim();
+        if line.starts_with('[') {
+            if let (Some(name), Some(path), Some(url)) =
+                (name.take(), path.take(), url.take())
+            {
+                submodules.push((name, path, url))
+            }
+            name = line.strip_prefix("[submodule").and_then(|rest|
+                rest.trim().strip_suffix(']')).map(|rest|
+                    rest.trim().trim_matches('"').to_string());
+            continue
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "path" => path = Some(value),
+                "url" => url = Some(value),
+                _ => (),
+            }
+        }
+    }
+    submodules
+}
+
+impl Repo {
+    fn head_commit(&self) -> Option<Oid> {
+        self.repo.head().ok()?.peel_to_commit().ok().map(|commit| commit.id())
+    }
+
+    fn submodules_at(&self, commit: Oid) -> Vec<Submodule> {
+        let tree = match self.repo.find_commit(commit)
+            .and_then(|commit| commit.tree())
+        {
+            Ok(tree) => tree,
+            Err(e) => {
+                log::warn!("Failed to get tree of commit {} in '{}': {}",
+                    commit, self.path.display(), e);
+                return vec![]
+            },
+        };
+        let blob = match tree.get_name(".gitmodules").map(
+            |entry| entry.to_object(&self.repo).and_then(
+                |object| object.peel_to_blob()))
+        {
+            Some(Ok(blob)) => blob,
+            _ => return vec![],
+        };
+        let content = String::from_utf8_lossy(blob.content());
+        let mut submodules = vec![];
+        for (name, path, url) in parse_gitmodules(&content) {
+            let relative = url.starts_with("./") || url.starts_with("../");
+            let url = resolve_url(&self.url, &url);
+            match tree.get_path(Path::new(&path)) {
+                Ok(entry) if entry.kind() == Some(ObjectType::Commit) =>
+                    submodules.push(Submodule {
+                        name, url, relative, commit: entry.id() }),
+                _ => log::warn!("Submodule '{}' of '{}' has no commit at {}",
+                        path, self.url, commit),
+            }
+        }
+        submodules
+    }
+}
+
+fn submodule_source(url: &str) -> Option<Source> {
+    // Sources are grouped by host, scp-like and local URLs have none
+    Url::from_str(url).ok()?.host_str()?;
+    Some(Source {
+        name: String::new(),
+        protocol: Protocol::Vcs { protocol: VcsProtocol::Git },
+        url: url.to_string(),
+        hash_url: xxh3_64(url.as_bytes()),
+        fragment: None,
+        ck: None,
+        md5: None,
+        sha1: None,
+        sha224: None,
+        sha256: None,
+        sha384: None,
+        sha512: None,
+        b2: None,
+    })
+}
+
+fn mirror_path(url: &str) -> PathBuf {
+    PathBuf::from(format!("sources/git/{:016x}", xxh3_64(url.as_bytes())))
+}
+
+/// Rewrite mirrored URLs, and relative submodules by name to the mirrors of
+/// the URLs they resolved to
+fn write_config(
+    urls: &BTreeSet<String>, relatives: &BTreeMap<String, Option<String>>
+) -> Result<()>
+{
+    if urls.is_empty() {
+        let _ = remove_file(CONFIG);
+        return Ok(())
+    }
+    let cwd = std::env::current_dir().map_err(|e| {
+        log::error!("Failed to get current dir: {}", e);
+        Error::IoError(e)
+    })?;
+    // Since git 2.38.1 submodules could not be cloned from local paths
+    let mut config = String::from("[protocol \"file\"]\n\tallow = always\n");
+    for url in urls.iter() {
+        config.push_str(&format!("[url \"{}\"]\n\tinsteadOf = {}\n",
+            cwd.join(mirror_path(url)).display(), url));
+    }
+    for (name, url) in relatives.iter() {
+        if let Some(url) = url.as_ref().filter(|url| urls.contains(*url)) {
+            config.push_str(&format!("[submodule \"{}\"]\n\turl = {}\n",
+                name, cwd.join(mirror_path(url)).display()));
+        }
+    }
+    write(CONFIG, config).map_err(|e| {
+        log::error!("Failed to write '{}': {}", CONFIG, e);
+        Error::IoError(e)
+    })
+}
+
+/// Mirror submodules of the git sources recursively, at their HEADs and the
+/// commits their fragments resolved to, and add them to the git sources
+pub(crate) fn cache_submodules(
+    git_sources: &mut Vec<Source>,
+    commits: Vec<(String, Oid)>,
+    hold: bool,
+    proxy: Option<&Proxy>,
+    gmr: Option<&Gmr>,
+    rules: &GitRules,
+    limits: &DomainLimits,
+    terminal: bool
+) -> Result<()>
+{
+    let mut seen: HashSet<u64> =
+        git_sources.iter().map(|source| source.hash_url).collect();
+    let mut visited = HashSet::new();
+    let mut urls = BTreeSet::new();
+    // None if the name is shared by relative submodules of different URLs
+    let mut relatives: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let mut pending: Vec<(String, Option<Oid>)> = git_sources.iter().map(
+        |source| (source.url.clone(), None)).collect();
+    pending.extend(commits.into_iter().map(|(url, commit)| (url, Some(commit))));
+    while ! pending.is_empty() {
+        let mut found = vec![];
+        for (url, commit) in pending.iter() {
+            let repo = match Repo::open_existing(mirror_path(url), url) {
+                Some(repo) => repo,
+                None => continue,
+            };
+            let commit = match commit.or_else(|| repo.head_commit()) {
+                Some(commit) => commit,
+                None => continue,
+            };
+            if visited.insert((url.clone(), commit)) {
+                found.append(&mut repo.submodules_at(commit))
+            }
+        }
+        let mut new_sources = vec![];
+        let mut next = vec![];
+        for submodule in found.into_iter() {
+            let source = match submodule_source(&submodule.url) {
+                Some(source) => source,
+                None => {
+                    log::warn!("Not mirroring submodule '{}' without a domain",
+                        submodule.url);
+                    continue
+                },
+            };
+            urls.insert(submodule.url.clone());
+            if submodule.relative {
+                let entry = relatives.entry(submodule.name.clone())
+                    .or_insert_with(|| Some(submodule.url.clone()));
+                if entry.as_ref().is_some_and(|url| url != &submodule.url) {
+                    log::warn!("Relative submodules named '{}' point to \
+                        different repos, they would be fetched from network",
+                        submodule.name);
+                    *entry = None
+                }
+            }
+            if seen.insert(source.hash_url) {
+                log::info!("Mirroring submodule '{}'", submodule.url);
+                new_sources.push(source)
+            }
+            next.push((submodule.url, Some(submodule.commit)));
+        }
+        if ! new_sources.is_empty() {
+            let mut repos_map = Source::to_repos_map(
+                Source::map_by_domain(&new_sources)?, "sources/git", gmr)?;
+            rules.apply(&mut repos_map);
+            // Submodules are never on AUR, so no auth for its RPC is needed
+            if Repo::sync_mt(repos_map, hold, proxy, &HttpAuth::default(),
+                limits, terminal).is_err()
+            {
+                log::warn!("Failed to mirror some submodules, they would be \
+                    fetched from network during build")
+            }
+            git_sources.append(&mut new_sources);
+        }
+        pending = next;
+    }
+    urls.retain(|url| Repo::open_existing(mirror_path(url), url)
+        .is_some_and(|repo| repo.head_commit().is_some()));
+    write_config(&urls, &relatives)
+}
+
+/// Let git in the command resolve submodules from their mirrors
+pub(crate) fn set_command_config(command: &mut Command) {
+    if ! Path::new(CONFIG).exists() {
+        return
+    }
+    match std::env::current_dir() {
+        Ok(cwd) => {
+            command.env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "include.path")
+                .env("GIT_CONFIG_VALUE_0", cwd.join(CONFIG));
+        },
+        Err(e) => log::warn!("Failed to get current dir, submodules would \
+            be fetched from network: {}", e),
+    }
+}
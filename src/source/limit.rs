@@ -0,0 +1,144 @@
+// Per domain limits of downloads and git fetches: how many run at the same
+// time, how many requests start per second, and how many bytes are received
+// per second, shared by all threads working on the same domain
+
+use std::{
+        collections::HashMap,
+        str::FromStr,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        thread::sleep,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+use url::Url;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::config::DomainLimit;
+
+struct Limiter {
+    threads: usize,
+    interval: Option<Duration>, // between request starts
+    next: Mutex<Instant>, // when the next request could start
+    bandwidth: u64, // bytes per second, 0 for unlimited
+    window: Mutex<(Instant, u64)>, // start of this second, bytes in it
+}
+
+impl Limiter {
+    fn new(limit: &DomainLimit) -> Self {
+        let interval = if limit.rps > 0.0 {
+            Some(Duration::from_secs_f64(1.0 / limit.rps))
+        } else {
+            None
+        };
+        Self {
+            threads: limit.threads,
+            interval,
+            next: Mutex::new(Instant::now()),
+            bandwidth: limit.bandwidth * 1024,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    fn wait_request(&self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let now = Instant::now();
+        let start = match self.next.lock() {
+            Ok(mut next) => {
+                let start = (*next).max(now);
+                *next = start + interval;
+                start
+            },
+            Err(_) => return,
+        };
+        if start > now {
+            sleep(start - now)
+        }
+    }
+
+    fn consume(&self, bytes: u64) {
+        if self.bandwidth == 0 {
+            return
+        }
+        let now = Instant::now();
+        let due = match self.window.lock() {
+            Ok(mut window) => {
+                if now.duration_since(window.0) >= Duration::from_secs(1) {
+                    *window = (now, 0)
+                }
+                window.1 += bytes;
+                window.0 + Duration::from_secs_f64(
+                    window.1 as f64 / self.bandwidth as f64)
+            },
+            Err(_) => return,
+        };
+        if due > now {
+            sleep(due - now)
+        }
+    }
+}
+
+/// Limits of all configured domains, cheap to clone into threads
+#[derive(Clone, Default)]
+pub(crate) struct DomainLimits {
+    limiters: Arc<HashMap<u64, Arc<Limiter>>>, // by xxh3 of domain
+}
+
+/// Bandwidth limiter of the domain a transfer comes from
+pub(crate) struct Throttle (Option<Arc<Limiter>>);
+
+impl Throttle {
+    pub(crate) fn consume(&self, bytes: u64) {
+        if let Some(limiter) = &self.0 {
+            limiter.consume(bytes)
+        }
+    }
+
+    pub(crate) fn is_limited(&self) -> bool {
+        self.0.as_ref().is_some_and(|limiter| limiter.bandwidth > 0)
+    }
+}
+
+impl DomainLimits {
+    pub(crate) fn new(limits: &HashMap<String, DomainLimit>) -> Self {
+        Self {
+            limiters: Arc::new(limits.iter().map(|(domain, limit)|
+                (xxh3_64(domain.as_bytes()), Arc::new(Limiter::new(limit))))
+                .collect())
+        }
+    }
+
+    /// Max concurrent jobs for the domain, as grouped by MapByDomain
+    pub(crate) fn threads(&self, domain: u64, default: usize) -> usize {
+        match self.limiters.get(&domain) {
+            Some(limiter) if limiter.threads > 0 => limiter.threads,
+            _ => default,
+        }
+    }
+
+    fn limiter(&self, url: &str) -> Option<Arc<Limiter>> {
+        if self.limiters.is_empty() {
+            return None
+        }
+        let url = Url::from_str(url).ok()?;
+        self.limiters.get(&xxh3_64(url.host_str()?.as_bytes())).cloned()
+    }
+
+    /// Wait until a request to the URL could start, then return the throttle
+    /// for its transfer
+    pub(crate) fn request(&self, url: &str) -> Throttle {
+        let limiter = self.limiter(url);
+        if let Some(limiter) = &limiter {
+            limiter.wait_request()
+        }
+        Throttle(limiter)
+    }
+}
@@ -0,0 +1,54 @@
+use std::{
+        collections::HashMap,
+        str::FromStr,
+    };
+
+use url::Url;
+
+/// Alternative locations of netfile sources, tried in order before the
+/// original URL. Only used for sources with integrity checksums, so a bad
+/// mirror could never sneak a different file in
+#[derive(Clone, Default)]
+pub(crate) struct NetfileMirrors {
+    rules: Vec<(String, Vec<String>)>, // Longest prefix first
+    cache: Option<String>,
+}
+
+impl NetfileMirrors {
+    pub(crate) fn new(rules: &HashMap<String, Vec<String>>, cache: &str)
+        -> Self
+    {
+        let mut rules: Vec<(String, Vec<String>)> = rules.iter().map(
+            |(prefix, mirrors)| (prefix.clone(), mirrors.clone())).collect();
+        rules.sort_unstable_by_key(
+            |(prefix, _)| std::cmp::Reverse(prefix.len()));
+        let cache = if cache.is_empty() {
+            None
+        } else {
+            Some(cache.trim_end_matches('/').to_string())
+        };
+        Self { rules, cache }
+    }
+
+    /// The local file cache server is tried first, laid out like gmr as
+    /// [prefix]/[host][path], then mirrors of the longest matching prefix
+    pub(super) fn candidates(&self, orig: &str) -> Vec<String> {
+        let mut candidates = vec![];
+        if let Some(cache) = &self.cache {
+            if let Ok(orig_url) = Url::from_str(orig) {
+                if let Some(host) = orig_url.host_str() {
+                    candidates.push(format!("{}/{}{}",
+                        cache, host, orig_url.path()))
+                }
+            }
+        }
+        if let Some((prefix, mirrors)) = self.rules.iter().find(
+            |(prefix, _)| orig.starts_with(prefix.as_str()))
+        {
+            for mirror in mirrors.iter() {
+                candidates.push(format!("{}{}", mirror, &orig[prefix.len()..]))
+            }
+        }
+        candidates
+    }
+}
@@ -1,14 +1,40 @@
+use std::{
+        fs::{
+            remove_file,
+            rename,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        str::FromStr,
+    };
+
+use url::Url;
+
 use crate::{
         error::{
             Error,
             Result
         },
         source::{
-            download,
+            blob,
+            cksums::Integ,
+            download::{
+                self,
+                HttpAgents,
+                Validators,
+            },
             protocol::{
                 NetfileProtocol,
                 Protocol,
             },
+            mirror::NetfileMirrors,
+            proxy::{
+                self,
+                Client,
+            },
+            quarantine,
             Proxy,
             Source,
         },
@@ -47,10 +73,24 @@ fn optional_update<C>(target: &mut Option<C>, source: &Option<C>)
     Ok(())
 }
 
+pub(super) fn has_integ(source: &Source) -> bool {
+    source.ck.is_some() || source.md5.is_some() || source.sha1.is_some() ||
+    source.sha224.is_some() || source.sha256.is_some() ||
+    source.sha384.is_some() || source.sha512.is_some() || source.b2.is_some()
+}
+
 pub(super) fn push_source(
     sources: &mut Vec<Source>, source: &Source
 ) -> Result<()>
 {
+    if ! has_integ(source) {  // SKIP sources could only be told apart by URL
+        if ! sources.iter().any(|source_cmp|
+            ! has_integ(source_cmp) && source_cmp.hash_url == source.hash_url)
+        {
+            sources.push(source.clone())
+        }
+        return Ok(())
+    }
     let mut existing = None;
     for source_cmp in sources.iter_mut() {
         if optional_equal(
@@ -97,12 +137,50 @@ pub(super) fn push_source(
         &mut existing.b2, &source.b2)
 }
 
+fn proxy_client(protocol: &NetfileProtocol) -> Client {
+    match protocol {
+        NetfileProtocol::Ftp => Client::Ftp,
+        NetfileProtocol::Http | NetfileProtocol::Https => Client::Http,
+        NetfileProtocol::File | NetfileProtocol::Rsync |
+            NetfileProtocol::Scp => Client::Unproxied,
+    }
+}
+
+fn download(
+    protocol: &NetfileProtocol,
+    actual_identity: &crate::identity::IdentityActual,
+    url: &str,
+    path: &Path,
+    agents: &HttpAgents,
+    proxy: Option<&str>,
+    terminal: bool
+) -> Result<()>
+{
+    match protocol {
+        NetfileProtocol::File =>
+            download::file(url, path),
+        NetfileProtocol::Ftp =>
+            download::ftp(url, path, proxy, agents.limits(), terminal),
+        NetfileProtocol::Http =>
+            download::http(url, path, agents, proxy, terminal),
+        NetfileProtocol::Https =>
+            download::http(url, path, agents, proxy, terminal),
+        NetfileProtocol::Rsync =>
+            download::rsync(actual_identity, url, path),
+        NetfileProtocol::Scp =>
+            download::scp(actual_identity, url, path),
+    }
+}
+
 pub(super) fn download_source(
     source: &Source,
-    integ_file: &super::cksums::IntegFile,
+    integs: &[Integ],
     actual_identity: &crate::identity::IdentityActual,
     skipint: bool,
-    proxy: Option<&Proxy>
+    proxy: Option<&Proxy>,
+    agents: &HttpAgents,
+    mirrors: &NetfileMirrors,
+    terminal: bool
 ) -> Result<()>
 {
     const MAX_TRIES: usize = 3;
@@ -114,42 +192,60 @@ pub(super) fn download_source(
             return Err(Error::ImpossibleLogic)
         };
     let url = source.url.as_str();
-    let mut proxy_actual = None;
-    let mut max_tries = MAX_TRIES;
-    let mut enable_proxy_at = MAX_TRIES;
-    if let Some(proxy) = proxy {
-        max_tries += proxy.after;
-        enable_proxy_at = proxy.after
-    };
-    for i in 0..max_tries {
-        if i == enable_proxy_at {
-            if i > 0 {
-                log::info!("Failed to download for {} times, using proxy", i);
+    let temp = blob::temp_path(integs)?;
+    let mut mirrored = false;
+    for mirror in mirrors.candidates(url) {
+        let mirror_protocol = match Url::from_str(&mirror).ok().and_then(
+            |mirror_url| Protocol::from_raw_string(mirror_url.scheme().as_bytes()))
+        {
+            Some(Protocol::Netfile { protocol }) => protocol,
+            _ => {
+                log::warn!("Ignored mirror '{}' with unsupported protocol",
+                    mirror);
+                continue
+            },
+        };
+        log::info!("Downloading '{}' from mirror '{}' to '{}'",
+            source.url, mirror, temp.display());
+        // Do not resume a partial download from another server, including
+        // one left by an earlier run
+        let _ = remove_file(&temp);
+        mirrored = true;
+        if download(&mirror_protocol, actual_identity, &mirror,
+            &temp, agents, None, terminal).is_ok()
+        {
+            if blob::verify(&temp, integs, skipint) {
+                if blob::store(&temp, integs).is_ok() {
+                    return Ok(())
+                }
+            } else {
+                quarantine::quarantine(&temp, &mirror, integs)
+            }
+        }
+    }
+    if mirrored {
+        let _ = remove_file(&temp);
+    }
+    let attempts = proxy::attempts(
+        proxy, url, proxy_client(protocol), MAX_TRIES);
+    for (i, attempt) in attempts.iter().enumerate() {
+        if i > 0 && attempts[i - 1] != *attempt {
+            if let Some(proxy) = attempt {
+                log::info!("Failed to download for {} times, using proxy '{}'",
+                    i, proxy);
             }
-            proxy_actual = proxy.and_then(
-                |proxy|Some(proxy.url.as_str()));
         }
-        let integ_file_temp = integ_file.temp()?;
         log::info!("Downloading '{}' to '{}', try {} of {}",
-            source.url, integ_file_temp.path.display(), i + 1, max_tries);
-        if match &protocol {
-            NetfileProtocol::File =>
-                download::file(url, &integ_file_temp.path),
-            NetfileProtocol::Ftp =>
-                download::ftp(actual_identity, url, &integ_file_temp.path),
-            NetfileProtocol::Http =>
-                download::http(url, &integ_file_temp.path, proxy_actual),
-            NetfileProtocol::Https =>
-                download::http(url, &integ_file_temp.path, proxy_actual),
-            NetfileProtocol::Rsync =>
-                download::rsync(actual_identity, url, &integ_file_temp.path),
-            NetfileProtocol::Scp =>
-                download::scp(actual_identity, url, &integ_file_temp.path),
-        }.is_ok() &&
-            integ_file_temp.valid(skipint)
+            source.url, temp.display(), i + 1, attempts.len());
+        if download(protocol, actual_identity, url, &temp,
+            agents, *attempt, terminal).is_ok()
         {
-            if integ_file.absorb(integ_file_temp).is_ok() {
-                return Ok(())
+            if blob::verify(&temp, integs, skipint) {
+                if blob::store(&temp, integs).is_ok() {
+                    return Ok(())
+                }
+            } else {
+                quarantine::quarantine(&temp, url, integs)
             }
         }
     }
@@ -159,51 +255,135 @@ pub(super) fn download_source(
 
 pub(super) fn cache_source(
     source: &Source,
-    integ_files: &Vec<super::cksums::IntegFile>,
+    integs: &[Integ],
     actual_identity: &crate::identity::IdentityActual,
     skipint: bool,
-    proxy: Option<&Proxy>
+    proxy: Option<&Proxy>,
+    agents: &HttpAgents,
+    mirrors: &NetfileMirrors,
+    terminal: bool
 ) -> Result<()>
 {
-    assert!(integ_files.len() > 0, "No integ files");
-    let mut good_files = vec![];
-    let mut bad_files = vec![];
-    for integ_file in integ_files.iter() {
-        log::info!("Caching '{}' to '{}'",
-            source.url,
-            integ_file.get_path().display());
-        if integ_file.valid(skipint) {
-            good_files.push(integ_file);
-        } else {
-            bad_files.push(integ_file);
+    assert!(integs.len() > 0, "No integrity checksums");
+    if let Some(sha256) = blob::lookup(integs) {
+        let path = blob::blob_path(&sha256);
+        log::info!("Found cached '{}' for '{}'", path.display(), source.url);
+        if blob::verify(&path, integs, skipint) {
+            // Other checksums of the same file might be new to the index
+            return blob::write_index(integs, &sha256)
         }
+        log::warn!("Cached '{}' does not match all checksums of '{}', \
+            downloading again", path.display(), source.url);
     }
-    let bad_count = bad_files.len();
-    if bad_count > 0 {
-        log::info!("Missing integ files for '{}': {}",
-                source.url, bad_count);
+    download_source(source, integs, actual_identity, skipint, proxy, agents,
+        mirrors, terminal)
+}
+
+/// Sources without integrity checksums are cached by URL instead
+pub(super) fn url_cache_path(source: &Source) -> PathBuf {
+    PathBuf::from(format!("sources/file-url/{:016x}", source.hash_url))
+}
+
+fn url_validators_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".validators");
+    PathBuf::from(name)
+}
+
+fn refresh_url_source(
+    source: &Source,
+    protocol: &NetfileProtocol,
+    actual_identity: &crate::identity::IdentityActual,
+    path: &Path,
+    agents: &HttpAgents,
+    proxy: Option<&str>,
+    terminal: bool
+) -> Result<()>
+{
+    let url = source.url.as_str();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".temp");
+    let temp = PathBuf::from(temp);
+    let validators_path = url_validators_path(path);
+    let validators = match protocol {
+        NetfileProtocol::Http | NetfileProtocol::Https => {
+            let validators = if path.exists() {
+                Validators::from_file(&validators_path)
+            } else {
+                Validators::default()
+            };
+            match download::http_conditional(
+                url, &temp, agents, proxy, &validators, terminal)?
+            {
+                Some(validators) => validators,
+                None => return Ok(()),
+            }
+        },
+        _ => {
+            download(protocol, actual_identity, url, &temp, agents, proxy,
+                terminal)?;
+            Validators::default()
+        },
+    };
+    if let Err(e) = rename(&temp, path) {
+        log::error!("Failed to rename '{}' to '{}': {}",
+            temp.display(), path.display(), e);
+        return Err(Error::IoError(e))
+    }
+    if validators.is_empty() {
+        let _ = remove_file(&validators_path);
+        Ok(())
     } else {
-        log::info!("All integ files healthy for '{}'", source.url);
-        return Ok(())
+        validators.to_file(&validators_path)
     }
-    let mut bad_count = 0;
-    while let Some(bad_file) = bad_files.pop() {
-        let r = match good_files.last() {
-            Some(good_file) =>
-                bad_file.clone_file_from(good_file),
-            None => download_source(
-                source, bad_file, actual_identity, skipint, proxy),
+}
+
+/// Cache a source without integrity checksums, revalidating the existing
+/// cache unless held, and falling back to it if the remote is unreachable
+pub(super) fn cache_url_source(
+    source: &Source,
+    actual_identity: &crate::identity::IdentityActual,
+    hold: bool,
+    proxy: Option<&Proxy>,
+    agents: &HttpAgents,
+    terminal: bool
+) -> Result<()>
+{
+    const MAX_TRIES: usize = 3;
+    let protocol =
+        if let Protocol::Netfile{protocol} = &source.protocol{
+            protocol
+        } else {
+            log::error!("Non-netfile source encountered by netfile cacher");
+            return Err(Error::ImpossibleLogic)
         };
-        match r {
-            Ok(_) => good_files.push(bad_file),
-            Err(_) => bad_count += 1,
+    let path = url_cache_path(source);
+    if hold && path.exists() {
+        log::info!("Holding cached '{}' for '{}'", path.display(), source.url);
+        return Ok(())
+    }
+    let attempts = proxy::attempts(
+        proxy, &source.url, proxy_client(protocol), MAX_TRIES);
+    for (i, attempt) in attempts.iter().enumerate() {
+        if i > 0 && attempts[i - 1] != *attempt {
+            if let Some(proxy) = attempt {
+                log::info!("Failed to download for {} times, using proxy '{}'",
+                    i, proxy);
+            }
+        }
+        log::info!("Refreshing '{}' from '{}', try {} of {}",
+            path.display(), source.url, i + 1, attempts.len());
+        if refresh_url_source(source, protocol, actual_identity, &path,
+            agents, *attempt, terminal).is_ok()
+        {
+            return Ok(())
         }
     }
-    if bad_count > 0 {
-        log::error!("Bad files still existing after download for '{}' ({})",
-                    source.url, bad_count);
-        Err(Error::IntegrityError)
-    } else {
-        Ok(())
+    if path.exists() {
+        log::warn!("Failed to refresh '{}', using the existing cache '{}'",
+            source.url, path.display());
+        return Ok(())
     }
-}
\ No newline at end of file
+    log::error!("Failed to download netfile source '{}'", source.url);
+    Err(Error::IntegrityError)
+}
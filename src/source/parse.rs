@@ -12,7 +12,12 @@ use crate::{
         source::{
             cksums::Sum,
             netfile::push_source as push_netfile_source,
-            git::push_source as push_git_source,
+            pgp::normalize_fingerprint,
+            git::{
+                Fragment,
+                push_source as push_git_source,
+            },
+            vcs::push_source as push_vcs_source,
             Source,
             VcsProtocol,
             Protocol,
@@ -33,6 +38,7 @@ fn push_source(
     protocol: Option<Protocol>,
     url: Option<String>,
     hash_url: u64,
+    fragment: Option<Fragment>,
     ck: Option<Cksum>,     // 32-bit CRC
     md5: Option<Md5sum>,   // 128-bit MD5
     sha1: Option<Sha1sum>,  // 160-bit SHA-1
@@ -43,18 +49,6 @@ fn push_source(
     b2: Option<B2sum>,    // 512-bit Blake-2B
 ) -> Result<()>
 {
-    if let None = ck {
-    if let None = md5 {
-    if let None = sha1 {
-    if let None = sha224 {
-    if let None = sha256 {
-    if let None = sha384 {
-    if let None = sha512 {
-    if let None = b2 {
-    if let Some(protocol) = &protocol {
-    if let Protocol::Netfile { protocol: _ } = protocol {
-        return Ok(()) // Skip netfiles that do not have integ
-    }}}}}}}}}}
     if let Some(name) = name {
         if let Some(protocol) = protocol {
             if let Some(url) = url {
@@ -63,6 +57,7 @@ fn push_source(
                     protocol,
                     url,
                     hash_url,
+                    fragment,
                     ck,
                     md5,
                     sha1,
@@ -80,7 +75,9 @@ fn push_source(
     Err(Error::BrokenPKGBUILDs(vec![]))
 }
 
-pub(crate) fn get_sources<P> (pkgbuild: P) -> Result<Vec<Source>>
+/// Sources and the normalized validpgpkeys of a PKGBUILD
+pub(crate) fn get_sources<P> (pkgbuild: P)
+    -> Result<(Vec<Source>, Vec<String>)>
 where
     P: AsRef<Path>
 {
@@ -96,6 +93,7 @@ where
     let mut protocol = None;
     let mut url = None;
     let mut hash_url = 0;
+    let mut fragment = None;
     let mut ck = None;
     let mut md5 = None;
     let mut sha1 = None;
@@ -105,6 +103,7 @@ where
     let mut sha512 = None;
     let mut b2 = None;
     let mut sources = vec![];
+    let mut validpgpkeys = vec![];
     let mut started = false;
     for line in  output.stdout.split(|byte| byte == &b'\n') {
         if line.len() == 0 {
@@ -113,7 +112,7 @@ where
         if line == b"[source]" {
             if started {
                 push_source(&mut sources,
-                    name, protocol, url, hash_url,
+                    name, protocol, url, hash_url, fragment,
                     ck, md5, sha1,
                     sha224, sha256, sha384, sha512,
                     b2)?;
@@ -121,6 +120,7 @@ where
                 protocol = None;
                 url = None;
                 hash_url = 0;
+                fragment = None;
                 ck = None;
                 md5 = None;
                 sha1 = None;
@@ -139,6 +139,8 @@ where
         let key = it.next().expect("Failed to get key");
         let value = it.next().expect("Failed to get value");
         match key {
+            b"validpgpkey" => validpgpkeys.push(normalize_fingerprint(
+                &String::from_utf8_lossy(value))),
             b"name" => {
                 name = Some(String::from_utf8_lossy(value).into_owned());
             }
@@ -153,6 +155,7 @@ where
                 url = Some(String::from_utf8_lossy(value).into_owned());
                 hash_url = xxh3_64(value);
             }
+            b"fragment" => fragment = Fragment::from_raw_string(value),
             b"cksum" => ck = Cksum::from_hex(value),
             b"md5sum" => md5 = Md5sum::from_hex(value),
             b"sha1sum" => sha1 = Sha1sum::from_hex(value),
@@ -168,36 +171,37 @@ where
         }
     }
     push_source(&mut sources,
-        name, protocol, url, hash_url,
+        name, protocol, url, hash_url, fragment,
         ck, md5, sha1,
         sha224, sha256, sha384, sha512,
         b2)?;
-    Ok(sources)
+    Ok((sources, validpgpkeys))
 }
 
 
-pub(crate) fn unique_sources(sources: &Vec<&Source>)
-    -> Result<(Vec<Source>, Vec<Source>, Vec<Source>)>
+// netfile, git, other VCS, local
+pub(crate) type UniqueSources =
+    (Vec<Source>, Vec<Source>, Vec<Source>, Vec<Source>);
+
+pub(crate) fn unique_sources(sources: &Vec<&Source>) -> Result<UniqueSources>
 {
     let mut local_sources: Vec<Source> = vec![];
     let mut git_sources: Vec<Source> = vec![];
     let mut netfile_sources: Vec<Source> = vec![];
+    let mut vcs_sources: Vec<Source> = vec![];
     for source in sources.iter() {
         match &source.protocol {
             Protocol::Netfile { protocol: _ } =>
                 push_netfile_source(&mut netfile_sources, source)?,
             Protocol::Vcs { protocol } => {
-                match protocol {  // Ignore VCS sources we do not support
-                    VcsProtocol::Bzr => (),
-                    VcsProtocol::Fossil => (),
+                match protocol {  // Other VCSes are cached with their CLIs
                     VcsProtocol::Git =>
                         push_git_source(&mut git_sources, source),
-                    VcsProtocol::Hg => (),
-                    VcsProtocol::Svn => (),
+                    _ => push_vcs_source(&mut vcs_sources, source),
                 }
             },
             Protocol::Local => local_sources.push(source.to_owned().to_owned())
         }
     }
-    Ok((netfile_sources, git_sources, local_sources))
+    Ok((netfile_sources, git_sources, vcs_sources, local_sources))
 }
@@ -0,0 +1,234 @@
+// TODO: Use native openpgp implementation
+// Detached signatures (.sig, .asc, .sign) among netfile sources are verified
+// against the fingerprints in validpgpkeys, using a gpg homedir we manage at
+// state/gnupg populated from the keyring dir. Good results are remembered as
+// sources/pgp/<signed blob sha256>-<signature sha256>
+
+use std::{
+        fs::{
+            DirBuilder,
+            read_dir,
+            read_to_string,
+            write,
+        },
+        os::unix::fs::DirBuilderExt,
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::{
+            Command,
+            Stdio,
+        },
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        identity::IdentityActual,
+        source::{
+            blob,
+            cksums::sha256_string,
+            Integ,
+            netfile,
+            Protocol,
+            Source,
+        },
+    };
+
+const HOMEDIR: &str = "state/gnupg";
+pub(super) const VERIFIED: &str = "sources/pgp";
+const EXTENSIONS: [&str; 3] = [".sig", ".asc", ".sign"];
+
+/// Fingerprints as makepkg wants them, upper case without spaces
+pub(crate) fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.chars().filter(|c| ! c.is_whitespace())
+        .collect::<String>().to_uppercase()
+}
+
+/// Import all key files under the keyring dir into our gpg homedir
+pub(crate) fn prepare_keyring(keyring: &str, actual_identity: &IdentityActual)
+    -> Result<()>
+{
+    if let Err(e) = DirBuilder::new().recursive(true).mode(0o700)
+        .create(HOMEDIR)
+    {
+        log::error!("Failed to create gpg homedir '{}': {}", HOMEDIR, e);
+        return Err(Error::IoError(e))
+    }
+    if let Err(e) = DirBuilder::new().recursive(true).create(VERIFIED) {
+        log::error!("Failed to create '{}': {}", VERIFIED, e);
+        return Err(Error::IoError(e))
+    }
+    let mut keys: Vec<PathBuf> = match read_dir(keyring) {
+        Ok(readdir) => readdir.flatten().map(|entry| entry.path())
+            .filter(|path| path.is_file()).collect(),
+        Err(e) => {
+            log::warn!("Failed to read keyring dir '{}': {}", keyring, e);
+            return Ok(())
+        },
+    };
+    if keys.is_empty() {
+        log::warn!("No keys in keyring dir '{}'", keyring);
+        return Ok(())
+    }
+    keys.sort_unstable();
+    crate::child::output_and_check(
+        actual_identity.set_root_drop_command(
+            Command::new("/usr/bin/gpg")
+                .arg("--homedir")
+                .arg(HOMEDIR)
+                .arg("--batch")
+                .arg("--import")
+                .args(&keys))
+                .stdin(Stdio::null()),
+        "to import keys into gpg homedir"
+    )
+}
+
+/// The cached file of a netfile source, and its sha256 if in the blob store
+fn cached(source: &Source) -> Option<(PathBuf, Option<String>)> {
+    if ! matches!(source.protocol, Protocol::Netfile { protocol: _ }) {
+        return None
+    }
+    let integs = Integ::vec_from_source(source);
+    if integs.is_empty() {
+        return Some((netfile::url_cache_path(source), None))
+    }
+    blob::lookup(&integs).map(
+        |sha256| (blob::blob_path(&sha256), Some(sha256)))
+}
+
+/// Run gpg and return the primary key fingerprint of a good signature
+fn gpg_verify(
+    signature: &Path, data: &Path, actual_identity: &IdentityActual
+) -> std::result::Result<String, String>
+{
+    let output = actual_identity.set_root_drop_command(
+        Command::new("/usr/bin/gpg")
+            .arg("--homedir")
+            .arg(HOMEDIR)
+            .arg("--batch")
+            .arg("--status-fd")
+            .arg("1")
+            .arg("--verify")
+            .arg(signature)
+            .arg(data))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to spawn gpg: {}", e))?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    let mut good = false;
+    let mut primary = None;
+    for line in status.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("[GNUPG:]") {
+            continue
+        }
+        let fields: Vec<&str> = fields.collect();
+        match fields.first().copied() {
+            Some("GOODSIG") => good = true,
+            Some("VALIDSIG") => primary = fields.get(10).map(
+                |fingerprint| fingerprint.to_string()),
+            Some("NO_PUBKEY") => return Err(format!(
+                "unknown public key {}, add it to the keyring dir",
+                fields.get(1).unwrap_or(&"?"))),
+            Some("BADSIG") => return Err(String::from("bad signature")),
+            Some("EXPKEYSIG") => return Err(String::from(
+                "signed with an expired key")),
+            Some("REVKEYSIG") => return Err(String::from(
+                "signed with a revoked key")),
+            _ => (),
+        }
+    }
+    match primary {
+        Some(primary) if good && output.status.success() => Ok(primary),
+        _ => Err(format!("gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim())),
+    }
+}
+
+fn verify_signature(
+    signature: &Source, data: &Source, validpgpkeys: &[String],
+    actual_identity: &IdentityActual
+) -> std::result::Result<(), String>
+{
+    let (signature_path, signature_sha256) = cached(signature)
+        .ok_or_else(|| format!("signature '{}' not cached", signature.name))?;
+    let (data_path, data_sha256) = cached(data)
+        .ok_or_else(|| format!("signed file '{}' not cached", data.name))?;
+    let record = match &data_sha256 {
+        Some(data_sha256) => {
+            let signature_sha256 = match signature_sha256 {
+                Some(signature_sha256) => signature_sha256,
+                None => sha256_string(&signature_path).map_err(
+                    |_| format!("failed to hash '{}'", signature.name))?,
+            };
+            Some(PathBuf::from(VERIFIED).join(
+                format!("{}-{}", data_sha256, signature_sha256)))
+        },
+        None => None,
+    };
+    if let Some(record) = &record {
+        if let Ok(primary) = read_to_string(record) {
+            if validpgpkeys.contains(&primary.trim().to_string()) {
+                return Ok(())
+            }
+        }
+    }
+    let primary = gpg_verify(&signature_path, &data_path, actual_identity)?;
+    if ! validpgpkeys.contains(&primary) {
+        return Err(format!("signed by {}, which is not in validpgpkeys",
+            primary))
+    }
+    if let Some(record) = &record {
+        if let Err(e) = write(record, format!("{}\n", primary)) {
+            log::warn!("Failed to record verification '{}': {}",
+                record.display(), e)
+        }
+    }
+    Ok(())
+}
+
+/// Verify all detached signatures among the sources of a PKGBUILD
+pub(crate) fn verify_sources(
+    pkgbase: &str, sources: &[Source], validpgpkeys: &[String],
+    actual_identity: &IdentityActual
+) -> Result<()>
+{
+    let mut bad = false;
+    for signature in sources.iter() {
+        let stem = match EXTENSIONS.iter().find_map(
+            |extension| signature.name.strip_suffix(extension))
+        {
+            Some(stem) => stem,
+            None => continue,
+        };
+        let data = match sources.iter().find(|data| data.name == stem) {
+            Some(data) => data,
+            None => {
+                log::warn!("PKGBUILD '{}': signed file '{}' of signature \
+                    '{}' is not a source, not verifying it",
+                    pkgbase, stem, signature.name);
+                continue
+            },
+        };
+        match verify_signature(signature, data, validpgpkeys, actual_identity)
+        {
+            Ok(()) => log::info!("PKGBUILD '{}': good signature '{}' for '{}'",
+                pkgbase, signature.name, data.name),
+            Err(e) => {
+                log::error!("PKGBUILD '{}': failed to verify signature '{}' \
+                    for '{}': {}", pkgbase, signature.name, data.name, e);
+                bad = true
+            },
+        }
+    }
+    if bad {
+        Err(Error::BrokenPKGBUILDs(vec![pkgbase.to_string()]))
+    } else {
+        Ok(())
+    }
+}
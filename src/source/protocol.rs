@@ -9,7 +9,7 @@ pub(super) enum NetfileProtocol {
     Scp,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub(super) enum VcsProtocol {
     Bzr,
     Fossil,
@@ -1,12 +1,181 @@
+// Proxies are only used to retry after direct tries failed. The first rule
+// with a domain glob matching the URL's host picks the proxies, tried one
+// after another, otherwise the default proxy is used. Hosts excluded like in
+// no_proxy, or by a rule of only 'direct', are never proxied. libgit2 only
+// talks HTTP to proxies, so git reaches SOCKS5 proxies through a local bridge
+// and SOCKS4 ones are skipped for it
+
+mod bridge;
+
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::{
+        config::ProxyRule,
+        error::Result,
+    };
+
+const DIRECT: &str = "direct";
+
+/// What would go through the proxy, as they support different proxy schemes
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Client {
+    Git,
+    Http,
+    Ftp,
+    Cli, // VCS CLIs, which get the proxy in env
+    Unproxied, // file, rsync and scp, which never use the proxy
+}
+
+impl Client {
+    fn supports(&self, proxy: &str) -> bool {
+        let scheme = match proxy.split_once("://") {
+            Some((scheme, _)) => scheme,
+            None => "http",
+        };
+        match self {
+            Self::Git => matches!(scheme,
+                "http" | "https" | "socks" | "socks5" | "socks5h"),
+            Self::Http => matches!(scheme,
+                "http" | "socks4" | "socks4a" | "socks" | "socks5"),
+            Self::Ftp => scheme == "http",
+            Self::Cli => true,
+            Self::Unproxied => false,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct Proxy {
-    pub(super) url: String,
+    default: Option<String>,
+    rules: Vec<ProxyRule>, // First match wins
+    no_proxy: Vec<String>,
     pub(super) after: usize,
 }
 
+/// Case-insensitive match of a glob where '*' matches any run of characters
+fn glob_match(glob: &str, text: &str) -> bool {
+    let (prefix, rest) = match glob.split_once('*') {
+        Some(split) => split,
+        None => return glob.eq_ignore_ascii_case(text),
+    };
+    match text.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => (),
+        _ => return false,
+    }
+    let text = &text[prefix.len()..];
+    (0..=text.len()).filter(|i| text.is_char_boundary(*i))
+        .any(|i| glob_match(rest, &text[i..]))
+}
+
+/// Whether a no_proxy entry excludes the host: '*' for all, a domain for
+/// itself and its subdomains, or a glob
+fn excluded(entry: &str, host: &str) -> bool {
+    if entry.contains('*') {
+        return glob_match(entry, host)
+    }
+    let entry = entry.trim_start_matches('.');
+    if entry.is_empty() {
+        return false
+    }
+    host.eq_ignore_ascii_case(entry) ||
+        host.len() > entry.len() &&
+        host.is_char_boundary(host.len() - entry.len() - 1) &&
+        host[host.len() - entry.len() - 1..].eq_ignore_ascii_case(
+            &format!(".{}", entry))
+}
+
 impl Proxy {
-    pub(crate) fn new(url: &str, after: usize) -> Self {
-        Self { url: url.to_string(), after }
+    /// The default proxy and the per domain rules, None if there's no proxy
+    /// at all. Exclusions in no_proxy and NO_PROXY env are also honored
+    pub(crate) fn new(
+        default: &str, after: usize, rules: &[ProxyRule], no_proxy: &[String]
+    ) -> Option<Self>
+    {
+        if default.is_empty() && rules.is_empty() {
+            return None
+        }
+        let mut no_proxy = no_proxy.to_vec();
+        for env in ["no_proxy", "NO_PROXY"] {
+            if let Ok(value) = std::env::var(env) {
+                no_proxy.extend(value.split(',').map(str::trim)
+                    .filter(|entry| ! entry.is_empty()).map(String::from))
+            }
+        }
+        Some(Self {
+            default: if default.is_empty() {
+                None
+            } else {
+                Some(default.to_string())
+            },
+            rules: rules.to_vec(),
+            no_proxy,
+            after,
+        })
+    }
+
+    /// All proxies that could be used for any URL
+    pub(super) fn urls(&self) -> impl Iterator<Item = &str> {
+        self.default.iter().chain(
+            self.rules.iter().flat_map(|rule| rule.proxies.iter()))
+            .map(String::as_str)
+            .filter(|proxy| *proxy != DIRECT)
+    }
+
+    /// Proxies to retry the URL through in order, empty if it should only be
+    /// reached directly
+    fn chain(&self, url: &str) -> Vec<&str> {
+        let url = Url::from_str(url).ok();
+        if let Some(host) = url.as_ref().and_then(|url| url.host_str()) {
+            if self.no_proxy.iter().any(|entry| excluded(entry, host)) {
+                return vec![]
+            }
+            if let Some(rule) = self.rules.iter().find(|rule|
+                rule.domains.iter().any(|glob| glob_match(glob, host)))
+            {
+                return rule.proxies.iter().map(String::as_str)
+                    .filter(|proxy| *proxy != DIRECT).collect()
+            }
+        }
+        self.default.iter().map(String::as_str).collect()
+    }
+}
+
+/// The proxy URL to give libgit2, a local bridge for a SOCKS5 proxy
+pub(super) fn for_git(proxy: &str) -> Result<String> {
+    if bridge::is_socks5(proxy) {
+        bridge::bridged(proxy)
+    } else {
+        Ok(proxy.to_string())
     }
 }
 
+/// The proxy to use for each try of the URL, None for direct: only direct
+/// tries if there's no proxy for it, otherwise the lazy direct tries and then
+/// the tries through each proxy the client supports
+pub(super) fn attempts<'a>(
+    proxy: Option<&'a Proxy>, url: &str, client: Client, tries: usize
+) -> Vec<Option<&'a str>>
+{
+    let proxy = match proxy {
+        Some(proxy) => proxy,
+        None => return vec![None; tries],
+    };
+    let chain: Vec<&str> = proxy.chain(url).into_iter().filter(|chained| {
+        let supported = client.supports(chained);
+        if ! supported {
+            log::warn!("Skipped proxy '{}' for '{}' as it's not supported \
+                by {:?} client", chained, url, client)
+        }
+        supported
+    }).collect();
+    if chain.is_empty() {
+        return vec![None; tries]
+    }
+    let mut attempts = vec![None; proxy.after];
+    for chained in chain {
+        attempts.extend(vec![Some(chained); tries])
+    }
+    attempts
+}
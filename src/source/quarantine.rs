@@ -0,0 +1,129 @@
+// Downloads failing their integrity checks are moved to sources/quarantine
+// instead of being deleted, each with a [name].meta file recording where it
+// came from and how it mismatched, to tell a truncated download from an
+// upstream that silently replaced a file
+
+use std::{
+        fs::{
+            create_dir_all,
+            read_dir,
+            read_to_string,
+            remove_file,
+            rename,
+            write,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        time::SystemTime,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::cksums::Integ,
+    };
+
+const QUARANTINE: &str = "sources/quarantine";
+
+fn meta_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".meta");
+    PathBuf::from(name)
+}
+
+/// Move the mismatched file into quarantine, or remove it if that fails
+pub(super) fn quarantine(path: &Path, url: &str, integs: &[Integ]) {
+    if let Err(e) = try_quarantine(path, url, integs) {
+        log::warn!("Failed to quarantine '{}', removing it: {}",
+            path.display(), e);
+        let _ = remove_file(path);
+    }
+}
+
+fn try_quarantine(path: &Path, url: &str, integs: &[Integ]) -> Result<()> {
+    let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let size = path.metadata()?.len();
+    let mut meta = format!("url: {}\ntime: {}\nsize: {}\n",
+        url, time.as_secs(), size);
+    for integ in integs.iter() {
+        let actual = match integ.sum_file(path) {
+            Some(actual) => actual.sum_string(),
+            None => String::from("(unreadable)"),
+        };
+        meta.push_str(&format!("{}sum: expected {} actual {}\n",
+            integ.algo(), integ.sum_string(), actual));
+    }
+    let stem = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = stem.trim_end_matches(".temp");
+    let target = PathBuf::from(QUARANTINE).join(
+        format!("{}-{}", time.as_millis(), stem));
+    create_dir_all(QUARANTINE)?;
+    rename(path, &target)?;
+    write(meta_path(&target), meta)?;
+    log::warn!("Quarantined '{}' downloaded from '{}' as '{}'",
+        path.display(), url, target.display());
+    Ok(())
+}
+
+fn entries() -> Vec<String> {
+    let readdir = match read_dir(QUARANTINE) {
+        Ok(readdir) => readdir,
+        Err(_) => return vec![],
+    };
+    let mut names: Vec<String> = readdir.flatten().filter_map(|entry| {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".meta") {
+            None
+        } else {
+            Some(name)
+        }
+    }).collect();
+    names.sort_unstable();
+    names
+}
+
+/// Print all quarantined files with their metadata, oldest first
+pub(crate) fn list() {
+    for name in entries() {
+        let path = PathBuf::from(QUARANTINE).join(&name);
+        println!("{}", name);
+        match read_to_string(meta_path(&path)) {
+            Ok(meta) => for line in meta.lines() {
+                println!("  {}", line)
+            },
+            Err(_) => println!("  (no metadata)"),
+        }
+    }
+}
+
+/// Remove the quarantined files along with their metadata, all if none given
+pub(crate) fn purge(names: &[String]) -> Result<()> {
+    let names = if names.is_empty() {
+        entries()
+    } else {
+        names.to_vec()
+    };
+    let mut r = Ok(());
+    for name in names.iter() {
+        if name.contains('/') {
+            log::error!("Quarantined name '{}' must not contain '/'", name);
+            r = Err(Error::InvalidArgument);
+            continue
+        }
+        let path = PathBuf::from(QUARANTINE).join(name);
+        if let Err(e) = remove_file(&path) {
+            log::error!("Failed to remove quarantined '{}': {}",
+                path.display(), e);
+            r = Err(e.into());
+            continue
+        }
+        let _ = remove_file(meta_path(&path));
+        log::info!("Purged quarantined '{}'", name);
+    }
+    r
+}
@@ -0,0 +1,210 @@
+// Caches for VCS sources other than git, maintained with their own CLIs and
+// laid out the way makepkg expects to find them in SRCDEST
+
+use std::{
+        fs::{
+            remove_dir_all,
+            remove_file,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        process::Command,
+    };
+
+use crate::{
+        child::output_and_check,
+        error::{
+            Error,
+            Result
+        },
+        identity::IdentityActual,
+        source::{
+            DomainLimits,
+            Protocol,
+            proxy::{
+                self,
+                Client,
+            },
+            Proxy,
+            Source,
+            VcsProtocol,
+        },
+    };
+
+impl VcsProtocol {
+    pub(super) fn cache_dir(&self) -> &'static str {
+        match self {
+            VcsProtocol::Bzr => "sources/bzr",
+            VcsProtocol::Fossil => "sources/fossil",
+            VcsProtocol::Git => "sources/git",
+            VcsProtocol::Hg => "sources/hg",
+            VcsProtocol::Svn => "sources/svn",
+        }
+    }
+}
+
+fn vcs_protocol(source: &Source) -> Result<&VcsProtocol> {
+    match &source.protocol {
+        Protocol::Vcs { protocol } => Ok(protocol),
+        _ => {
+            log::error!("Non-VCS source encountered by VCS cacher");
+            Err(Error::ImpossibleLogic)
+        },
+    }
+}
+
+pub(super) fn cache_path(protocol: &VcsProtocol, source: &Source) -> PathBuf {
+    PathBuf::from(format!("{}/{:016x}", protocol.cache_dir(), source.hash_url))
+}
+
+pub(super) fn push_source(sources: &mut Vec<Source>, source: &Source) {
+    for source_cmp in sources.iter() {
+        if source.hash_url == source_cmp.hash_url {
+            return
+        }
+    }
+    sources.push(source.clone())
+}
+
+/// Whether the cache looks like what makepkg would have created itself, so
+/// it would be used as-is with --holdver
+pub(super) fn healthy(source: &Source) -> bool {
+    let protocol = match vcs_protocol(source) {
+        Ok(protocol) => protocol,
+        Err(_) => return false,
+    };
+    let path = cache_path(protocol, source);
+    match protocol {
+        VcsProtocol::Bzr => path.join(".bzr").is_dir(),
+        VcsProtocol::Fossil => path.is_file(),
+        VcsProtocol::Git => false,
+        VcsProtocol::Hg => path.join(".hg").is_dir(),
+        VcsProtocol::Svn => path.join(".svn").is_dir(),
+    }
+}
+
+fn remove_broken(path: &Path) {
+    let r = if path.is_dir() {
+        remove_dir_all(path)
+    } else if path.exists() {
+        remove_file(path)
+    } else {
+        return
+    };
+    if let Err(e) = r {
+        log::warn!("Failed to remove broken VCS cache '{}': {}",
+            path.display(), e)
+    }
+}
+
+fn command_clone(protocol: &VcsProtocol, url: &str, path: &Path)
+    -> Result<Command>
+{
+    let mut command;
+    match protocol {
+        VcsProtocol::Bzr => {
+            command = Command::new("/usr/bin/bzr");
+            command.arg("branch").arg(url).arg(path).arg("--no-tree");
+        },
+        VcsProtocol::Fossil => {
+            command = Command::new("/usr/bin/fossil");
+            command.arg("clone").arg(url).arg(path);
+        },
+        VcsProtocol::Hg => {
+            command = Command::new("/usr/bin/hg");
+            command.arg("clone").arg("-U").arg(url).arg(path);
+        },
+        VcsProtocol::Svn => {
+            command = Command::new("/usr/bin/svn");
+            command.arg("checkout").arg(url).arg(path);
+        },
+        VcsProtocol::Git => {
+            log::error!("Git source encountered by VCS cacher");
+            return Err(Error::ImpossibleLogic)
+        },
+    }
+    Ok(command)
+}
+
+fn command_update(protocol: &VcsProtocol, url: &str, path: &Path)
+    -> Result<Command>
+{
+    let mut command;
+    match protocol {
+        VcsProtocol::Bzr => {
+            command = Command::new("/usr/bin/bzr");
+            command.arg("pull").arg("--directory").arg(path).arg(url);
+        },
+        VcsProtocol::Fossil => {
+            command = Command::new("/usr/bin/fossil");
+            command.arg("pull").arg("-R").arg(path).arg(url);
+        },
+        VcsProtocol::Hg => {
+            command = Command::new("/usr/bin/hg");
+            command.arg("pull").arg("-R").arg(path).arg(url);
+        },
+        VcsProtocol::Svn => {
+            command = Command::new("/usr/bin/svn");
+            command.arg("update").arg(path);
+        },
+        VcsProtocol::Git => {
+            log::error!("Git source encountered by VCS cacher");
+            return Err(Error::ImpossibleLogic)
+        },
+    }
+    Ok(command)
+}
+
+pub(super) fn cache_source(
+    source: &Source,
+    actual_identity: &IdentityActual,
+    proxy: Option<&Proxy>,
+    limits: &DomainLimits
+) -> Result<()>
+{
+    const MAX_TRIES: usize = 3;
+    let protocol = vcs_protocol(source)?;
+    let path = cache_path(protocol, source);
+    let mut r = Ok(());
+    let attempts =
+        proxy::attempts(proxy, &source.url, Client::Cli, MAX_TRIES);
+    for (i, attempt) in attempts.iter().enumerate() {
+        if i > 0 && attempts[i - 1] != *attempt {
+            if let Some(proxy) = attempt {
+                log::info!("Failed to sync for {} times, using proxy '{}'",
+                    i, proxy);
+            }
+        }
+        let (mut command, job) = if healthy(source) {
+            (command_update(protocol, &source.url, &path)?,
+                format!("update {:?} source '{}' at '{}'",
+                    protocol, source.url, path.display()))
+        } else {
+            remove_broken(&path);
+            (command_clone(protocol, &source.url, &path)?,
+                format!("clone {:?} source '{}' into '{}'",
+                    protocol, source.url, path.display()))
+        };
+        if let Some(proxy) = attempt {
+            command.env("http_proxy", proxy).env("https_proxy", proxy)
+                .env("all_proxy", proxy);
+        }
+        log::info!("Trying to {}, try {} of {}", job, i + 1, attempts.len());
+        // The CLIs transfer on their own, so only the request rate applies
+        if limits.request(&source.url).is_limited() {
+            log::warn!("Bandwidth limit not applied to {}", job)
+        }
+        actual_identity.set_root_drop_command(&mut command);
+        r = output_and_check(&mut command, &job);
+        if r.is_ok() {
+            return r
+        }
+    }
+    log::error!("Failed to cache VCS source '{}'", source.url);
+    if ! healthy(source) {
+        remove_broken(&path)
+    }
+    r
+}